@@ -0,0 +1,114 @@
+use std::{iter::Peekable, str::Chars};
+
+use crate::shell::Shell;
+
+/** How deeply variables can refer to other variables before giving up, since `a=b b=a` would go on forever */
+const MAX_DEPTH: usize = 100;
+
+/** Evaluates an integer expression, like the value given to a `declare -i` variable. Supports + - * / % with the
+usual precedence, parentheses, and variable names, whose values are evaluated too (unset or empty is 0). The error is
+the message to show */
+pub fn evaluate(expression: &str, shell: &Shell) -> Result<i64, String> {
+    return evaluate_at(expression, shell, 0)
+        .map_err(|error| format!("{}: {}", expression.trim(), error));
+}
+
+fn evaluate_at(expression: &str, shell: &Shell, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err("expression recursion level exceeded".to_string());
+    }
+    let mut chars = expression.chars().peekable();
+    if skip_whitespace(&mut chars).is_none() {
+        return Ok(0);
+    }
+    let value = sum(&mut chars, shell, depth)?;
+    return match skip_whitespace(&mut chars) {
+        None => Ok(value),
+        Some(_) => Err(format!(
+            "syntax error in expression (error token is \"{}\")",
+            chars.collect::<String>()
+        )),
+    };
+}
+
+/** `a + b - c`, the lowest precedence */
+fn sum(chars: &mut Peekable<Chars>, shell: &Shell, depth: usize) -> Result<i64, String> {
+    let mut value = product(chars, shell, depth)?;
+    while let Some(operator @ ('+' | '-')) = skip_whitespace(chars) {
+        chars.next();
+        let right = product(chars, shell, depth)?;
+        value = match operator {
+            '+' => value.wrapping_add(right),
+            _ => value.wrapping_sub(right),
+        };
+    }
+    return Ok(value);
+}
+
+/** `a * b / c % d` */
+fn product(chars: &mut Peekable<Chars>, shell: &Shell, depth: usize) -> Result<i64, String> {
+    let mut value = unary(chars, shell, depth)?;
+    while let Some(operator @ ('*' | '/' | '%')) = skip_whitespace(chars) {
+        chars.next();
+        let right = unary(chars, shell, depth)?;
+        value = match operator {
+            '*' => value.wrapping_mul(right),
+            _ if right == 0 => return Err("division by 0".to_string()),
+            '/' => value.wrapping_div(right),
+            _ => value.wrapping_rem(right),
+        };
+    }
+    return Ok(value);
+}
+
+/** A number, variable, or parenthesized expression, with any signs before it */
+fn unary(chars: &mut Peekable<Chars>, shell: &Shell, depth: usize) -> Result<i64, String> {
+    return match skip_whitespace(chars) {
+        Some('-') => {
+            chars.next();
+            Ok(unary(chars, shell, depth)?.wrapping_neg())
+        }
+        Some('+') => {
+            chars.next();
+            unary(chars, shell, depth)
+        }
+        Some('(') => {
+            chars.next();
+            let value = sum(chars, shell, depth)?;
+            if skip_whitespace(chars) != Some(')') {
+                return Err("missing `)'".to_string());
+            }
+            chars.next();
+            Ok(value)
+        }
+        Some('0'..='9') => {
+            let number = take_while(chars, |c| c.is_ascii_alphanumeric());
+            number
+                .parse()
+                .map_err(|_| format!("value too great for base (error token is \"{}\")", number))
+        }
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            let name = take_while(chars, |c| c.is_ascii_alphanumeric() || c == '_');
+            evaluate_at(&shell.var(&name).unwrap_or_default(), shell, depth + 1)
+        }
+        Some(_) => Err(format!(
+            "syntax error: operand expected (error token is \"{}\")",
+            chars.collect::<String>()
+        )),
+        None => Err("syntax error: operand expected".to_string()),
+    };
+}
+
+/** Skips to the next character that isn't whitespace, returning it without taking it */
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    return chars.peek().copied();
+}
+
+fn take_while(chars: &mut Peekable<Chars>, predicate: impl Fn(char) -> bool) -> String {
+    let mut taken = String::new();
+    while let Some(c) = chars.next_if(|c| predicate(*c)) {
+        taken.push(c);
+    }
+    return taken;
+}
@@ -0,0 +1,162 @@
+/** Every builtin's name, usage, and what it does, in name order */
+const BUILTINS: [(&str, &str, &str); 38] = [
+    (
+        ".",
+        ". filename [arguments]",
+        "Runs the commands in a file in this shell",
+    ),
+    (
+        "[",
+        "[ expression ]",
+        "Evaluates a conditional expression, like test",
+    ),
+    (
+        "alias",
+        "alias [name[=value] ...]",
+        "Defines or lists aliases",
+    ),
+    (
+        "bg",
+        "bg [job_spec]",
+        "Continues a stopped job in the background",
+    ),
+    ("cd", "cd [dir | -]", "Changes the current directory"),
+    (
+        "command",
+        "command [-vV] command [arg ...]",
+        "Runs a command without looking for an alias, or describes it",
+    ),
+    (
+        "declare",
+        "declare [-i|+i] [name[=value] ...]",
+        "Gives variables attributes, like being an integer",
+    ),
+    ("dirs", "dirs", "Shows the directory stack"),
+    (
+        "echo",
+        "echo [-neE] [arg ...]",
+        "Writes its arguments to stdout",
+    ),
+    ("env", "env", "Lists the exported variables"),
+    ("eval", "eval [arg ...]", "Runs its arguments as a command"),
+    (
+        "exit",
+        "exit [n]",
+        "Exits the shell with status n, or the last command's status",
+    ),
+    (
+        "export",
+        "export [-n] [name[=value] ...]",
+        "Makes variables go to the programs that are run",
+    ),
+    ("false", "false", "Fails"),
+    ("fg", "fg [job_spec]", "Brings a job to the foreground"),
+    (
+        "hash",
+        "hash [-r] [-p pathname] [name ...]",
+        "Remembers or shows where commands were found",
+    ),
+    (
+        "help",
+        "help [builtin]",
+        "Lists the builtins, or shows how to use one",
+    ),
+    (
+        "history",
+        "history [-c] [n]",
+        "Shows the command history, or clears it",
+    ),
+    ("jobs", "jobs [-lp]", "Lists the running and stopped jobs"),
+    (
+        "kill",
+        "kill [-sigspec] pid | jobspec ... or kill -l",
+        "Sends a signal to processes",
+    ),
+    (
+        "popd",
+        "popd",
+        "Removes the top of the directory stack and changes to it",
+    ),
+    (
+        "printenv",
+        "printenv [name ...]",
+        "Prints exported variables",
+    ),
+    (
+        "printf",
+        "printf [-v var] format [arguments]",
+        "Writes its arguments formatted",
+    ),
+    (
+        "pushd",
+        "pushd [dir]",
+        "Adds a directory to the stack and changes to it",
+    ),
+    ("pwd", "pwd [-LP]", "Prints the current directory"),
+    (
+        "read",
+        "read [-r] [name ...]",
+        "Reads a line into variables",
+    ),
+    (
+        "set",
+        "set [-eCx] [-o option] [arg ...]",
+        "Changes options or the positional parameters",
+    ),
+    (
+        "shift",
+        "shift [n]",
+        "Drops the first n positional parameters",
+    ),
+    (
+        "source",
+        "source filename [arguments]",
+        "Runs the commands in a file in this shell",
+    ),
+    (
+        "test",
+        "test [expression]",
+        "Evaluates a conditional expression",
+    ),
+    (
+        "timeout",
+        "timeout duration command [arg ...]",
+        "Runs a command, stopping it if it's still running after the duration",
+    ),
+    (
+        "trap",
+        "trap [-lp] [[action] signal_spec ...]",
+        "Runs a command when the shell gets SIGINT or exits",
+    ),
+    ("true", "true", "Succeeds"),
+    (
+        "type",
+        "type [-a] name [name ...]",
+        "Shows how each name would be run",
+    ),
+    (
+        "umask",
+        "umask [-S] [mode]",
+        "Shows or sets the file creation mask",
+    ),
+    ("unalias", "unalias [-a] name [name ...]", "Removes aliases"),
+    ("unset", "unset [-v] name [name ...]", "Removes variables"),
+    (
+        "which",
+        "which [-a] name [name ...]",
+        "Shows the programs on PATH each name would run",
+    ),
+];
+
+/** The name of every builtin, for completion */
+pub fn names() -> Vec<&'static str> {
+    return BUILTINS.iter().map(|(name, _, _)| *name).collect();
+}
+
+/** A builtin's usage and description */
+pub fn find(name: &str) -> Option<(&'static str, &'static str)> {
+    return BUILTINS
+        .iter()
+        .find(|(builtin_name, _, _)| *builtin_name == name)
+        .map(|(_, usage, description)| (*usage, *description));
+}
@@ -0,0 +1,82 @@
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+/** What a color is used for */
+#[derive(Clone, Copy)]
+pub enum Role {
+    Prompt,
+    Error,
+    /** A command that was found, in the line being typed */
+    Command,
+    /** A command that wasn't found, in the line being typed */
+    UnknownCommand,
+    Comment,
+    /** The suggested rest of the line being typed */
+    Hint,
+}
+
+impl Role {
+    /** The name in `SHELL_COLORS`, and the SGR code used when it isn't set there */
+    fn name_and_default(&self) -> (&str, &str) {
+        return match self {
+            Role::Prompt => ("prompt", "32"),
+            Role::Error => ("error", "31"),
+            Role::Command => ("command", "32"),
+            Role::UnknownCommand => ("unknown", "31"),
+            Role::Comment => ("comment", "2"),
+            Role::Hint => ("hint", "2"),
+        };
+    }
+}
+
+/** The prompt in its color, when stdout is a terminal */
+pub fn prompt(text: &str) -> String {
+    return paint(text, Role::Prompt, io::stdout().is_terminal());
+}
+
+/** An error message in its color, when stderr is a terminal */
+pub fn error(text: &str) -> String {
+    return paint(text, Role::Error, io::stderr().is_terminal());
+}
+
+/** Part of the line being typed in its color. The line editor only highlights when it's on a terminal */
+pub fn highlight(text: &str, role: Role) -> String {
+    return paint(text, role, true);
+}
+
+/** The text wrapped in the role's color, unless it's not going to a terminal or colors are off. Trailing newlines are
+kept outside the color, so it doesn't run into the next line */
+fn paint(text: &str, role: Role, terminal: bool) -> String {
+    let code = match code(role) {
+        Some(code) if terminal => code,
+        _ => return text.to_string(),
+    };
+    let content = text.trim_end_matches('\n');
+    if content.is_empty() {
+        return text.to_string();
+    }
+    return format!("\x1b[{}m{}\x1b[0m{}", code, content, &text[content.len()..]);
+}
+
+/** The SGR code for the role, from the exported `SHELL_COLORS` (like `prompt=1;34:error=31`) or the default. `NO_COLOR`
+turns every color off, and an empty code (like `error=`) turns off just that one */
+fn code(role: Role) -> Option<String> {
+    if env::var_os("NO_COLOR").is_some_and(|no_color| !no_color.is_empty()) {
+        return None;
+    }
+    let (name, default) = role.name_and_default();
+    let configured = env::var("SHELL_COLORS").ok().and_then(|colors| {
+        colors.split(':').find_map(|entry| {
+            let (entry_name, code) = entry.split_once('=')?;
+            return (entry_name == name).then(|| code.to_string());
+        })
+    });
+    let code = configured.unwrap_or(default.to_string());
+    // anything other than numbers and ; could be an escape sequence of its own
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+        return None;
+    }
+    return Some(code);
+}
@@ -1,201 +1,2515 @@
 use std::{
     env,
-    fs::File,
-    io::Write,
-    path::PathBuf,
-    process::{exit, Stdio},
-    str::FromStr,
+    ffi::OsString,
+    fs::{self, File},
+    io::{ErrorKind, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::ExitStatusExt,
+    },
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
+use rustyline::history::{History, SearchDirection};
 use tokio::net::unix::pipe::{Receiver, Sender};
 
-use crate::parser::RedirectType;
+use crate::{
+    arithmetic, builtins, color, conditional, expand, glob,
+    parser::{self, RedirectType},
+    printf,
+    shell::{self, Shell},
+    signal,
+};
+
+/** How long `timeout` waits for a command to stop after SIGTERM before killing it */
+const TIMEOUT_GRACE: Duration = Duration::from_secs(2);
+
+/** How deeply evals can nest before giving up, since `eval eval eval ...` (or an eval that evals itself through a variable) could go on forever */
+const MAX_EVAL_DEPTH: usize = 100;
 
 #[derive(Debug)]
 pub enum Command {
-    Exit,
+    Exit(Vec<String>),
     Echo(Vec<String>),
-    Type(Vec<Command>),
-    PWD,
+    /** Whether to list every match (-a), and the commands to describe */
+    Type(bool, Vec<Command>),
+    PWD(Vec<String>),
     CD(Vec<String>),
-    Executable(PathBuf, Vec<String>),
+    History(Vec<String>),
+    Printf(Vec<String>),
+    Source(Vec<String>),
+    Set(Vec<String>),
+    Shift(Vec<String>),
+    Eval(Vec<String>),
+    Alias(Vec<String>),
+    Unalias(Vec<String>),
+    True,
+    False,
+    /** Whether it was run as `[`, which needs a closing `]`, and the args */
+    Test(bool, Vec<String>),
+    Kill(Vec<String>),
+    Jobs(Vec<String>),
+    Fg(Vec<String>),
+    Bg(Vec<String>),
+    Read(Vec<String>),
+    Umask(Vec<String>),
+    Pushd(Vec<String>),
+    Popd(Vec<String>),
+    Dirs(Vec<String>),
+    Env,
+    Printenv(Vec<String>),
+    Hash(Vec<String>),
+    /** `command`, which runs the command after it without looking for an alias, or with -v or -V describes it */
+    CommandBuiltin(Vec<String>),
+    Which(Vec<String>),
+    /** `declare [-i|+i] [name[=value] ...]`, which gives variables attributes */
+    Declare(Vec<String>),
+    /** `export [-n] [name[=value] ...]`, which makes variables go to programs that are run (or stop with -n) */
+    Export(Vec<String>),
+    Unset(Vec<String>),
+    Help(Vec<String>),
+    /** `trap [action] signal ...`, which sets a command to run when the shell gets SIGINT, SIGTERM, or SIGHUP, or
+    exits */
+    Trap(Vec<String>),
+    /** `timeout duration command [arg ...]`, which stops the command if it's still running after the duration */
+    Timeout(Vec<String>),
+    /** The name it was run by, the file it runs, and the arguments */
+    Executable(String, PathBuf, Vec<String>),
     InvalidCommand(String),
     Pipe(Box<Command>, Box<Command>),
-    Redirect(RedirectType, RedirectType, Box<Command>),
+    /** Redirects, as the file descriptor and where it goes, applied in order */
+    Redirect(Vec<(i32, RedirectType)>, Box<Command>),
+    /** `left && right`, right only runs if left succeeds */
+    And(Box<Command>, Box<Command>),
+    /** `left || right`, right only runs if left fails */
+    Or(Box<Command>, Box<Command>),
+    /** `left; right` */
+    Sequence(Box<Command>, Box<Command>),
+    /** `(commands)`, run without changing the shell's directory, variables, or options */
+    Subshell(Box<Command>),
+    /** `{ commands; }`, run in this shell so they can share one redirect */
+    Group(Box<Command>),
+    /** `if condition; then commands; else commands; fi`, where an `elif` is another If as the else */
+    If(Box<Command>, Box<Command>, Option<Box<Command>>),
+    /** `while condition; do commands; done`, or `until` if the bool is set, which loops until the condition succeeds */
+    While(bool, Box<Command>, Box<Command>),
+    /** `for name in words; do commands; done`, with the words unexpanded until the loop runs */
+    For(String, Vec<String>, Box<Command>),
+    /** `case word in pattern | pattern) commands;; esac`, with each clause's patterns and commands (if it has any) */
+    Case(String, Vec<(Vec<String>, Option<Command>)>),
+    /** `time pipeline`, which runs the pipeline and then shows how long it took */
+    Time(Box<Command>),
+    /** `command &`, with the text of the command for listing it as a job */
+    Background(Box<Command>, String),
+    /** Words that haven't been expanded yet, which become one of the other commands when run */
+    Simple(Vec<String>),
 }
 
 impl Command {
-    pub async fn run(&self) {
-        self.run_with_io(IO::Default, IO::Default, IO::Default)
-            .await
-            .wait()
+    /** Runs the command, returning the exit code if the shell should exit */
+    pub async fn run(&self, shell: &mut Shell) -> Option<i32> {
+        let mut result = self
+            .run_with_io(shell, IO::Default, IO::Default, IO::Stderr)
             .await;
+        result.wait_errexit(shell).await;
+        if let RunResult::Exit(exit_code) = result {
+            return Some(exit_code);
+        }
+        return None;
+    }
+
+    /** Runs the command with its output collected in memory instead of printed, like for `$(...)`. Returns the output and exit code */
+    pub async fn capture(&self, shell: &mut Shell) -> (Vec<u8>, i32) {
+        let (sender, receiver) = tokio::net::unix::pipe::pipe().unwrap();
+        let mut output = IO::Buffer(Vec::new());
+        // read while the command runs, since it would block once the pipe fills up
+        let run = async {
+            let out = IO::Pipe(Some(sender), None);
+            let mut result = self.run_with_io(shell, IO::Default, out, IO::Stderr).await;
+            return result.wait().await;
+        };
+        let (exit_code, _) = tokio::join!(run, output.read_from(receiver));
+        return (output.into_bytes(), exit_code);
     }
 
     /** Runs a command with the given io (in, out, err). Returns a run result to be waited on. */
-    async fn run_with_io(&self, mut iin: IO, mut out: IO, mut err: IO) -> RunResult {
+    async fn run_with_io(
+        &self,
+        shell: &mut Shell,
+        mut iin: IO,
+        mut out: IO,
+        mut err: IO,
+    ) -> RunResult {
         match self {
-            Command::Exit => exit(0),
-            Command::Echo(args) => out.writeln(args.join(" ")).await,
-            Command::Type(commands) => {
+            Command::Exit(args) => {
+                if args.len() > 1 {
+                    err.writeln(format!("{}: too many arguments", self.name()))
+                        .await;
+                    return RunResult::Code(1);
+                }
+                // without a code it exits with the status of the last command
+                let exit_code = match args.first().map(|arg| arg.parse::<i64>()) {
+                    None => shell.last_status,
+                    Some(Ok(exit_code)) => exit_code.rem_euclid(256) as i32,
+                    Some(Err(_)) => {
+                        err.writeln(format!(
+                            "{}: {}: numeric argument required",
+                            self.name(),
+                            args[0]
+                        ))
+                        .await;
+                        2
+                    }
+                };
+                return RunResult::Exit(exit_code);
+            }
+            Command::Echo(args) => {
+                let mut newline = true;
+                let mut escapes = false;
+                let mut args = args.as_slice();
+                // only leading words made up entirely of flags are options, so `echo a -n` prints the -n
+                while let Some(flags) = args.first().and_then(|arg| arg.strip_prefix('-')) {
+                    if flags.is_empty() || !flags.chars().all(|c| "neE".contains(c)) {
+                        break;
+                    }
+                    for flag in flags.chars() {
+                        match flag {
+                            'n' => newline = false,
+                            'e' => escapes = true,
+                            _ => escapes = false,
+                        }
+                    }
+                    args = &args[1..];
+                }
+
+                let mut output = args.join(" ");
+                if escapes {
+                    let (unescaped, stopped) = printf::unescape(&output);
+                    output = unescaped;
+                    // \c stops all output, including the newline
+                    newline &= !stopped;
+                }
+                if newline {
+                    out.writeln(output).await;
+                } else {
+                    out.write(output).await;
+                }
+            }
+            Command::Type(all, commands) => {
+                let mut exit_code = 0;
                 for command in commands {
-                    out.writeln(command.r#type()).await;
+                    let alias = shell.aliases.get(command.lookup_name());
+                    // like other shells it fails if any name isn't anything
+                    if alias.is_none() && matches!(command, Command::InvalidCommand(..)) {
+                        exit_code = 1;
+                    }
+                    // aliases are checked first since they're expanded before anything else is looked up
+                    if let Some(alias) = alias {
+                        out.writeln(format!(
+                            "{} is aliased to '{}'",
+                            command.lookup_name(),
+                            alias
+                        ))
+                        .await;
+                        if !*all {
+                            continue;
+                        }
+                    }
+
+                    if *all {
+                        for r#type in command.type_all() {
+                            out.writeln(r#type).await;
+                        }
+                    } else {
+                        out.writeln(command.r#type()).await;
+                    }
                 }
+                return RunResult::Code(exit_code);
             }
-            Command::PWD => {
-                out.writeln(env::current_dir().unwrap().display().to_string())
-                    .await
+            Command::PWD(args) => {
+                // logical by default, so a cd through a symlink shows the path it went through
+                let mut physical = false;
+                for arg in args {
+                    match arg.as_str() {
+                        "-P" => physical = true,
+                        "-L" => physical = false,
+                        _ => {
+                            err.writeln(format!("{}: {}: invalid option", self.name(), arg))
+                                .await;
+                            return RunResult::Code(2);
+                        }
+                    }
+                }
+
+                let path = match (physical, env::current_dir()) {
+                    (false, _) => shell::working_dir(),
+                    (true, Ok(current_dir)) => current_dir.canonicalize().unwrap_or(current_dir),
+                    // the directory was deleted, so it doesn't have a real path any more
+                    (true, Err(_)) => {
+                        err.writeln(format!(
+                            "{}: error retrieving current directory: No such file or directory",
+                            self.name()
+                        ))
+                        .await;
+                        return RunResult::Code(1);
+                    }
+                };
+                out.writeln(path.display().to_string()).await;
             }
             Command::CD(args) => {
                 if args.len() > 2 {
                     err.writeln(format!("{}: too many arguments", self.name()))
                         .await;
-                    return RunResult::None;
+                    return RunResult::Code(1);
+                }
+
+                // `cd -` goes back to the last directory, and prints where it ended up
+                let going_back = args.get(0).map(|arg| arg == "-").unwrap_or(false);
+                let path_str = if going_back {
+                    match env::var("OLDPWD") {
+                        Ok(old_pwd) => old_pwd,
+                        Err(_) => {
+                            err.writeln(format!("{}: OLDPWD not set", self.name()))
+                                .await;
+                            return RunResult::Code(1);
+                        }
+                    }
+                } else {
+                    match args.get(0) {
+                        Some(arg) => arg.clone(),
+                        // without HOME, the home directory from the user's passwd entry is the next best thing
+                        None => match env::var_os("HOME").or_else(shell::passwd_home) {
+                            Some(home) => home.to_string_lossy().into_owned(),
+                            None => {
+                                err.writeln(format!("{}: HOME not set", self.name())).await;
+                                return RunResult::Code(1);
+                            }
+                        },
+                    }
+                };
+
+                let current_dir = match change_dir(&path_str) {
+                    Ok(current_dir) => current_dir,
+                    Err(reason) => {
+                        err.writeln(format!("{}: {}: {}", self.name(), path_str, reason))
+                            .await;
+                        return RunResult::Code(1);
+                    }
+                };
+                if going_back {
+                    out.writeln(current_dir.display().to_string()).await;
+                }
+            }
+            Command::History(args) => {
+                if args.len() > 1 {
+                    err.writeln(format!("{}: too many arguments", self.name()))
+                        .await;
+                    return RunResult::Code(1);
                 }
 
-                let path_str = args.get(0).map(|cp| cp.clone()).unwrap_or_else(|| {
-                    let home = env::var_os("HOME").unwrap();
-                    home.into_string().unwrap()
-                });
+                let history = shell.editor.history();
+                let mut start = 0;
+                if let Some(arg) = args.get(0) {
+                    if arg == "-c" {
+                        shell.editor.clear_history().unwrap();
+                        return RunResult::None;
+                    }
+                    let count = match arg.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            err.writeln(format!(
+                                "{}: {}: numeric argument required",
+                                self.name(),
+                                arg
+                            ))
+                            .await;
+                            return RunResult::Code(1);
+                        }
+                    };
+                    start = history.len().saturating_sub(count);
+                }
 
-                let path = PathBuf::from_str(&path_str).unwrap();
-                if !path.exists() {
+                let mut lines = String::new();
+                for index in start..history.len() {
+                    let entry = history
+                        .get(index, SearchDirection::Forward)
+                        .unwrap()
+                        .unwrap()
+                        .entry;
+                    lines += &format!("{:>5}  {}\n", index + 1, entry);
+                }
+                out.write(lines).await;
+            }
+            Command::Printf(args) => {
+                let mut args = args.as_slice();
+                let mut variable = None;
+                if args.first().map(|arg| arg == "-v").unwrap_or(false) {
+                    if args.len() < 2 {
+                        err.writeln(format!("{}: -v: option requires an argument", self.name()))
+                            .await;
+                        return RunResult::Code(2);
+                    }
+                    if !parser::is_name(&args[1]) {
+                        err.writeln(format!(
+                            "{}: `{}': not a valid identifier",
+                            self.name(),
+                            args[1]
+                        ))
+                        .await;
+                        return RunResult::Code(1);
+                    }
+                    variable = Some(&args[1]);
+                    args = &args[2..];
+                }
+                if args.is_empty() {
                     err.writeln(format!(
-                        "{}: {}: No such file or directory",
+                        "{}: usage: printf [-v var] format [arguments]",
+                        self.name()
+                    ))
+                    .await;
+                    return RunResult::Code(2);
+                }
+
+                let (output, errors) = printf::format(&args[0], &args[1..]);
+                // what could be formatted is still printed, but the command fails
+                let exit_code = if errors.is_empty() { 0 } else { 1 };
+                for error in errors {
+                    err.writeln(format!("{}: {}", self.name(), error)).await;
+                }
+                match variable {
+                    // like bash, a NUL ends the value since it can't be stored in the environment
+                    Some(variable) => shell.set_var(variable, output.split('\0').next().unwrap()),
+                    None => out.write(output).await,
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Source(args) => {
+                if args.is_empty() {
+                    err.writeln(format!("{}: filename argument required", self.name()))
+                        .await;
+                    return RunResult::Code(2);
+                }
+
+                let contents = match fs::read(&args[0]) {
+                    // a stray invalid byte shouldn't stop the rest of the file from running
+                    Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+                    Err(error) => {
+                        let reason = match error.kind() {
+                            ErrorKind::NotFound => "No such file or directory".to_string(),
+                            _ => error.to_string(),
+                        };
+                        err.writeln(format!("{}: {}: {}", self.name(), args[0], reason))
+                            .await;
+                        return RunResult::Code(1);
+                    }
+                };
+
+                return run_lines(&contents, shell, iin, out, err).await;
+            }
+            Command::Set(args) => {
+                if args.is_empty() {
+                    // both the shell's own variables and exported ones
+                    let mut variables: Vec<(String, String)> = env::vars_os()
+                        .map(|(name, value)| {
+                            (
+                                name.to_string_lossy().into_owned(),
+                                value.to_string_lossy().into_owned(),
+                            )
+                        })
+                        .chain(shell.variables.clone())
+                        .collect();
+                    variables.sort();
+                    let mut lines = String::new();
+                    for (name, value) in variables {
+                        lines += &format!("{}={}\n", name, quote(&value));
+                    }
+                    out.write(lines).await;
+                    return RunResult::Code(0);
+                }
+
+                let mut args = args.as_slice();
+                let mut set_positionals = false;
+                while let Some(arg) = args.first() {
+                    if arg == "--" {
+                        args = &args[1..];
+                        set_positionals = true;
+                        break;
+                    }
+                    let enable = arg.starts_with('-');
+                    if !(enable || arg.starts_with('+')) || arg.len() < 2 {
+                        break;
+                    }
+                    args = &args[1..];
+
+                    for flag in arg[1..].chars() {
+                        let option = match flag {
+                            'e' => "errexit",
+                            'x' => "xtrace",
+                            'C' => "noclobber",
+                            'o' => match args.first() {
+                                Some(name) => {
+                                    args = &args[1..];
+                                    name.as_str()
+                                }
+                                // `set -o` alone lists the options
+                                None => {
+                                    out.write(shell.options.list()).await;
+                                    return RunResult::Code(0);
+                                }
+                            },
+                            _ => {
+                                err.writeln(format!(
+                                    "{}: {}{}: invalid option",
+                                    self.name(),
+                                    &arg[..1],
+                                    flag
+                                ))
+                                .await;
+                                return RunResult::Code(2);
+                            }
+                        };
+                        if !shell.options.set(option, enable) {
+                            err.writeln(format!(
+                                "{}: {}: invalid option name",
+                                self.name(),
+                                option
+                            ))
+                            .await;
+                            return RunResult::Code(2);
+                        }
+                    }
+                }
+                if set_positionals || !args.is_empty() {
+                    shell.positional_parameters = args.to_vec();
+                }
+            }
+            Command::Shift(args) => {
+                if args.len() > 1 {
+                    err.writeln(format!("{}: too many arguments", self.name()))
+                        .await;
+                    return RunResult::Code(1);
+                }
+                let count = match args.get(0).map(|arg| arg.parse::<i64>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        err.writeln(format!(
+                            "{}: {}: numeric argument required",
+                            self.name(),
+                            args[0]
+                        ))
+                        .await;
+                        return RunResult::Code(1);
+                    }
+                };
+                if count < 0 || !shell.shift(count as usize) {
+                    err.writeln(format!(
+                        "{}: {}: shift count out of range",
                         self.name(),
-                        path_str
+                        count
                     ))
                     .await;
-                    return RunResult::None;
+                    return RunResult::Code(1);
                 }
-                if !path.is_dir() {
-                    err.writeln(format!("{}: {}: Not a directory", self.name(), path_str))
+            }
+            Command::Eval(args) => {
+                if shell.eval_depth >= MAX_EVAL_DEPTH {
+                    err.writeln(format!("{}: maximum nesting depth exceeded", self.name()))
                         .await;
+                    return RunResult::Code(1);
+                }
+                let command = parser::parse_input(&args.join(" "), shell);
+                if command.is_none() {
+                    return RunResult::None;
+                }
+
+                shell.eval_depth += 1;
+                let result = Box::pin(command.unwrap().run_with_io(shell, iin, out, err)).await;
+                shell.eval_depth -= 1;
+                return result;
+            }
+            Command::Alias(args) => {
+                if args.is_empty() {
+                    let mut aliases: Vec<_> = shell.aliases.iter().collect();
+                    aliases.sort();
+                    for (name, value) in aliases {
+                        out.writeln(format_alias(name, value)).await;
+                    }
+                    return RunResult::None;
+                }
+
+                // a name that isn't an alias doesn't stop the rest, but the command fails
+                let mut exit_code = 0;
+                for arg in args {
+                    match arg.split_once('=') {
+                        Some((name, value)) => {
+                            shell.aliases.insert(name.to_string(), value.to_string());
+                        }
+                        None => match shell.aliases.get(arg) {
+                            Some(value) => out.writeln(format_alias(arg, value)).await,
+                            None => {
+                                err.writeln(format!("{}: {}: not found", self.name(), arg))
+                                    .await;
+                                exit_code = 1;
+                            }
+                        },
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Unalias(args) => {
+                if args.is_empty() {
+                    err.writeln(format!(
+                        "{}: usage: unalias [-a] name [name ...]",
+                        self.name()
+                    ))
+                    .await;
+                    return RunResult::Code(2);
+                }
+                if args[0] == "-a" {
+                    shell.aliases.clear();
                     return RunResult::None;
                 }
-                env::set_current_dir(path).unwrap();
+                let mut exit_code = 0;
+                for arg in args {
+                    if shell.aliases.remove(arg).is_none() {
+                        err.writeln(format!("{}: {}: not found", self.name(), arg))
+                            .await;
+                        exit_code = 1;
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::True => return RunResult::Code(0),
+            Command::False => return RunResult::Code(1),
+            Command::Test(bracket, args) => {
+                let mut args = args.as_slice();
+                if *bracket {
+                    if args.last().map(|arg| arg != "]").unwrap_or(true) {
+                        err.writeln(format!("{}: missing `]'", self.name())).await;
+                        return RunResult::Code(2);
+                    }
+                    args = &args[..args.len() - 1];
+                }
+                return match conditional::evaluate(args) {
+                    Ok(true) => RunResult::Code(0),
+                    Ok(false) => RunResult::Code(1),
+                    Err(error) => {
+                        err.writeln(format!("{}: {}", self.name(), error)).await;
+                        RunResult::Code(2)
+                    }
+                };
+            }
+            Command::Kill(args) => {
+                let mut args = args.as_slice();
+                if args.first().map(|arg| arg == "-l").unwrap_or(false) {
+                    out.writeln(signal::names().join(" ")).await;
+                    return RunResult::Code(0);
+                }
+
+                let mut signal = libc::SIGTERM;
+                if let Some(name) = args.first().and_then(|arg| arg.strip_prefix('-')) {
+                    signal = match signal::parse(name) {
+                        Some(signal) => signal,
+                        None => {
+                            err.writeln(format!(
+                                "{}: {}: invalid signal specification",
+                                self.name(),
+                                name
+                            ))
+                            .await;
+                            return RunResult::Code(1);
+                        }
+                    };
+                    args = &args[1..];
+                }
+                if args.is_empty() {
+                    err.writeln(format!(
+                        "{}: usage: kill [-sigspec] pid | jobspec ... or kill -l",
+                        self.name()
+                    ))
+                    .await;
+                    return RunResult::Code(2);
+                }
+
+                let mut exit_code = 0;
+                for arg in args {
+                    let job = arg.starts_with('%').then(|| {
+                        let number = shell.jobs.find(Some(arg))?;
+                        return shell.jobs.iter().find(|job| job.number == number);
+                    });
+                    let pid = match (job, arg.parse::<libc::pid_t>()) {
+                        // the job's whole process group, so every program in a pipeline gets it
+                        (Some(Some(job)), _) => -(job.pid as libc::pid_t),
+                        (Some(None), _) => {
+                            err.writeln(format!("{}: {}: no such job", self.name(), arg))
+                                .await;
+                            exit_code = 1;
+                            continue;
+                        }
+                        (None, Ok(pid)) => pid,
+                        (None, Err(_)) => {
+                            err.writeln(format!(
+                                "{}: {}: arguments must be process or job IDs",
+                                self.name(),
+                                arg
+                            ))
+                            .await;
+                            exit_code = 1;
+                            continue;
+                        }
+                    };
+                    if unsafe { libc::kill(pid, signal) } != 0 {
+                        let error = std::io::Error::last_os_error();
+                        let reason = match error.raw_os_error() {
+                            Some(libc::ESRCH) => "No such process".to_string(),
+                            Some(libc::EPERM) => "Operation not permitted".to_string(),
+                            _ => error.to_string(),
+                        };
+                        err.writeln(format!("{}: ({}): {}", self.name(), pid, reason))
+                            .await;
+                        exit_code = 1;
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Jobs(args) => {
+                let (mut pids, mut long) = (false, false);
+                for arg in args {
+                    match arg.as_str() {
+                        "-p" => pids = true,
+                        "-l" => long = true,
+                        _ => {
+                            err.writeln(format!("{}: {}: invalid option", self.name(), arg))
+                                .await;
+                            return RunResult::Code(2);
+                        }
+                    }
+                }
+
+                shell.jobs.poll();
+                out.write(shell.jobs.list(long, pids)).await;
+                // like bash, finished jobs are only listed once
+                if !pids {
+                    shell.jobs.remove_finished();
+                }
+            }
+            Command::Fg(args) | Command::Bg(args) => {
+                let number = match shell.jobs.find(args.first().map(|arg| arg.as_str())) {
+                    Some(number) => number,
+                    None => {
+                        let spec = args.first().map(|arg| arg.as_str()).unwrap_or("current");
+                        err.writeln(format!("{}: {}: no such job", self.name(), spec))
+                            .await;
+                        return RunResult::Code(1);
+                    }
+                };
+
+                if let Command::Bg(..) = self {
+                    let job = shell.jobs.iter().find(|job| job.number == number).unwrap();
+                    // resumes the job if it was stopped, and does nothing if it's already running
+                    unsafe { libc::kill(job.pid as libc::pid_t, libc::SIGCONT) };
+                    out.writeln(format!(
+                        "[{}]{} {} &",
+                        job.number,
+                        shell.jobs.marker(job.number),
+                        job.command
+                    ))
+                    .await;
+                    return RunResult::Code(0);
+                }
+
+                let job = shell.jobs.remove(number).unwrap();
+                out.writeln(job.command.clone()).await;
+                // a job has a process group of its own, so the terminal is handed to it while it's in the foreground
+                // for Ctrl-C to reach it, and taken back once it's done
+                let (group, shell_group) =
+                    unsafe { (libc::getpgid(job.pid as libc::pid_t), libc::getpgrp()) };
+                let terminal = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+                let handed_over = terminal
+                    && group > 0
+                    && group != shell_group
+                    && unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, group) } == 0;
+                unsafe { libc::kill(job.pid as libc::pid_t, libc::SIGCONT) };
+                let mut result = RunResult::Child(job.child);
+                if !handed_over {
+                    return result;
+                }
+                let exit_code = result.wait().await;
+                unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, shell_group) };
+                return RunResult::Code(exit_code);
+            }
+            Command::Read(args) => {
+                let mut args = args.as_slice();
+                let raw = args.first().map(|arg| arg == "-r").unwrap_or(false);
+                if raw {
+                    args = &args[1..];
+                }
+                let default_name = ["REPLY".to_string()];
+                let names = if args.is_empty() {
+                    &default_name[..]
+                } else {
+                    args
+                };
+                if let Some(name) = names.iter().find(|name| !parser::is_name(name)) {
+                    err.writeln(format!(
+                        "{}: `{}': not a valid identifier",
+                        self.name(),
+                        name
+                    ))
+                    .await;
+                    return RunResult::Code(1);
+                }
+
+                let mut line = String::new();
+                let mut found = false;
+                while let Some(next) = read_input_line(shell, &mut iin).await {
+                    found = true;
+                    line += &next;
+                    // without -r a backslash at the end continues onto the next line
+                    if raw || !ends_with_escape(&line) {
+                        break;
+                    }
+                    line.pop();
+                }
+                if !raw {
+                    line = printf::remove_backslashes(&line);
+                }
+
+                // each name gets a field split on IFS, and the last gets whatever's left
+                let ifs = expand::ifs(shell);
+                let is_whitespace = |c: char| c.is_ascii_whitespace() && ifs.contains(c);
+                let mut rest = line.trim_matches(is_whitespace);
+                for (i, name) in names.iter().enumerate() {
+                    let value = if i == names.len() - 1 {
+                        rest
+                    } else {
+                        let (value, next) = expand::next_field(rest, &ifs);
+                        rest = next;
+                        value
+                    };
+                    shell.set_var(name, value);
+                }
+                return RunResult::Code(if found { 0 } else { 1 });
             }
-            Command::Executable(_, args) => {
-                let mut pcommand = tokio::process::Command::new(self.name());
-                // let mut pcommand = process::Command::new(self.name());
+            Command::Umask(args) => {
+                let mut args = args.as_slice();
+                let symbolic = args.first().map(|arg| arg == "-S").unwrap_or(false);
+                if symbolic {
+                    args = &args[1..];
+                }
+
+                if let Some(arg) = args.first() {
+                    let mask = match libc::mode_t::from_str_radix(arg, 8) {
+                        Ok(mask) if mask <= 0o777 => mask,
+                        _ => {
+                            err.writeln(format!(
+                                "{}: {}: octal number out of range",
+                                self.name(),
+                                arg
+                            ))
+                            .await;
+                            return RunResult::Code(1);
+                        }
+                    };
+                    unsafe { libc::umask(mask) };
+                    return RunResult::Code(0);
+                }
+
+                // umask can only be read by setting it, so it's put straight back
+                let mask = unsafe { libc::umask(0) };
+                unsafe { libc::umask(mask) };
+                if symbolic {
+                    out.writeln(symbolic_mode(0o777 & !mask)).await;
+                } else {
+                    out.writeln(format!("{:04o}", mask)).await;
+                }
+            }
+            Command::Pushd(args) => {
+                if args.len() > 1 {
+                    err.writeln(format!("{}: too many arguments", self.name()))
+                        .await;
+                    return RunResult::Code(1);
+                }
+
+                let previous_dir = shell::working_dir();
+                // without a directory, the top two entries swap places
+                let path = match args.first() {
+                    Some(arg) => PathBuf::from(arg),
+                    None => match shell.directory_stack.pop() {
+                        Some(top) => top,
+                        None => {
+                            err.writeln(format!("{}: no other directory", self.name()))
+                                .await;
+                            return RunResult::Code(1);
+                        }
+                    },
+                };
+                if let Err(reason) = change_dir(&path.to_string_lossy()) {
+                    err.writeln(format!("{}: {}: {}", self.name(), path.display(), reason))
+                        .await;
+                    if args.is_empty() {
+                        shell.directory_stack.push(path);
+                    }
+                    return RunResult::Code(1);
+                }
+                shell.directory_stack.push(previous_dir);
+                out.writeln(format_directory_stack(shell)).await;
+            }
+            Command::Popd(args) => {
+                if !args.is_empty() {
+                    err.writeln(format!("{}: too many arguments", self.name()))
+                        .await;
+                    return RunResult::Code(1);
+                }
+
+                let top = match shell.directory_stack.pop() {
+                    Some(top) => top,
+                    None => {
+                        err.writeln(format!("{}: directory stack empty", self.name()))
+                            .await;
+                        return RunResult::Code(1);
+                    }
+                };
+                if let Err(reason) = change_dir(&top.to_string_lossy()) {
+                    err.writeln(format!("{}: {}: {}", self.name(), top.display(), reason))
+                        .await;
+                    return RunResult::Code(1);
+                }
+                out.writeln(format_directory_stack(shell)).await;
+            }
+            Command::Dirs(args) => {
+                if !args.is_empty() {
+                    err.writeln(format!("{}: too many arguments", self.name()))
+                        .await;
+                    return RunResult::Code(1);
+                }
+                out.writeln(format_directory_stack(shell)).await;
+            }
+            Command::Env => {
+                for (name, value) in env::vars_os() {
+                    out.writeln(format!(
+                        "{}={}",
+                        name.to_string_lossy(),
+                        value.to_string_lossy()
+                    ))
+                    .await;
+                }
+            }
+            Command::Printenv(names) => {
+                if names.is_empty() {
+                    return Box::pin(Command::Env.run_with_io(shell, iin, out, err)).await;
+                }
+
+                // every name is looked up, but any missing one makes it fail
+                let mut found_all = true;
+                for name in names {
+                    match env::var_os(name) {
+                        Some(value) => out.writeln(value.to_string_lossy().into_owned()).await,
+                        None => found_all = false,
+                    }
+                }
+                return RunResult::Code(if found_all { 0 } else { 1 });
+            }
+            Command::Declare(args) => {
+                // -i makes the names integers and +i makes them plain again
+                let mut integer = None;
+                let mut args = args.as_slice();
+                while let Some(arg) = args.first() {
+                    match arg.as_str() {
+                        "-i" => integer = Some(true),
+                        "+i" => integer = Some(false),
+                        "--" => {
+                            args = &args[1..];
+                            break;
+                        }
+                        _ if arg.len() > 1 && arg.starts_with(['-', '+']) => {
+                            err.writeln(format!("{}: {}: invalid option", self.name(), arg))
+                                .await;
+                            err.writeln(format!(
+                                "{}: usage: declare [-i|+i] [name[=value] ...]",
+                                self.name()
+                            ))
+                            .await;
+                            return RunResult::Code(2);
+                        }
+                        _ => break,
+                    }
+                    args = &args[1..];
+                }
+
+                // without names it lists the integer variables, in a form that can be run to declare them again
+                if args.is_empty() {
+                    let mut names: Vec<&String> = shell.integer_variables.iter().collect();
+                    names.sort();
+                    for name in names {
+                        let value = shell.var(name).unwrap_or_default();
+                        out.writeln(format!("declare -i {}=\"{}\"", name, value))
+                            .await;
+                    }
+                    return RunResult::Code(0);
+                }
+
+                let mut exit_code = 0;
+                for arg in args {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value)),
+                        None => (arg.as_str(), None),
+                    };
+                    if !parser::is_name(name) {
+                        err.writeln(format!(
+                            "{}: `{}': not a valid identifier",
+                            self.name(),
+                            arg
+                        ))
+                        .await;
+                        exit_code = 1;
+                        continue;
+                    }
+                    match integer {
+                        Some(true) => shell.integer_variables.insert(name.to_string()),
+                        Some(false) => shell.integer_variables.remove(name),
+                        None => false,
+                    };
+                    let value = match value {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    match assignment_value(name, value, shell) {
+                        Ok(value) => shell.set_var(name, &value),
+                        Err(error) => {
+                            err.writeln(format!("{}: {}", self.name(), error)).await;
+                            exit_code = 1;
+                        }
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Export(args) => {
+                let mut args = args.as_slice();
+                let mut unexport = false;
+                while let Some(arg) = args.first() {
+                    match arg.as_str() {
+                        "-n" => unexport = true,
+                        "-p" => (),
+                        "--" => {
+                            args = &args[1..];
+                            break;
+                        }
+                        _ if arg.len() > 1 && arg.starts_with('-') => {
+                            err.writeln(format!("{}: {}: invalid option", self.name(), arg))
+                                .await;
+                            err.writeln(format!(
+                                "{}: usage: export [-n] [name[=value] ...]",
+                                self.name()
+                            ))
+                            .await;
+                            return RunResult::Code(2);
+                        }
+                        _ => break,
+                    }
+                    args = &args[1..];
+                }
+
+                // without names it lists what's exported, in a form that can be run to export them again
+                if args.is_empty() {
+                    let mut variables: Vec<_> = env::vars_os().collect();
+                    variables.sort();
+                    let mut lines = String::new();
+                    for (name, value) in variables {
+                        lines += &format!(
+                            "declare -x {}=\"{}\"\n",
+                            name.to_string_lossy(),
+                            value.to_string_lossy()
+                        );
+                    }
+                    out.write(lines).await;
+                    return RunResult::Code(0);
+                }
+
+                let mut exit_code = 0;
+                for arg in args {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value)),
+                        None => (arg.as_str(), None),
+                    };
+                    if !parser::is_name(name) {
+                        err.writeln(format!(
+                            "{}: `{}': not a valid identifier",
+                            self.name(),
+                            arg
+                        ))
+                        .await;
+                        exit_code = 1;
+                        continue;
+                    }
+                    let value = match value.map(|value| assignment_value(name, value, shell)) {
+                        Some(Ok(value)) => Some(value),
+                        Some(Err(error)) => {
+                            err.writeln(format!("{}: {}", self.name(), error)).await;
+                            exit_code = 1;
+                            continue;
+                        }
+                        None => None,
+                    };
+                    if unexport {
+                        if let Some(value) = value {
+                            shell.set_var(name, &value);
+                        }
+                        shell.unexport_var(name);
+                    } else {
+                        shell.export_var(name, value.as_deref());
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Unset(args) => {
+                let mut args = args.as_slice();
+                // -v is the default, functions (-f) don't exist
+                if args.first().map(|arg| arg == "-v").unwrap_or(false) {
+                    args = &args[1..];
+                }
+                let mut exit_code = 0;
+                for name in args {
+                    if !parser::is_name(name) {
+                        err.writeln(format!(
+                            "{}: `{}': not a valid identifier",
+                            self.name(),
+                            name
+                        ))
+                        .await;
+                        exit_code = 1;
+                        continue;
+                    }
+                    shell.unset_var(name);
+                    shell.integer_variables.remove(name);
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Help(names) => {
+                if names.is_empty() {
+                    let mut lines = String::new();
+                    for name in builtins::names() {
+                        let (_, description) = builtins::find(name).unwrap();
+                        lines += &format!("{:<10}{}\n", name, description);
+                    }
+                    out.write(lines).await;
+                    return RunResult::Code(0);
+                }
+
+                // every name is shown, but any that isn't a builtin makes it fail
+                let mut exit_code = 0;
+                for name in names {
+                    match builtins::find(name) {
+                        Some((usage, description)) => {
+                            out.writeln(format!("{}: {}\n    {}", name, usage, description))
+                                .await
+                        }
+                        None => {
+                            err.writeln(format!(
+                                "{}: no help topics match `{}'",
+                                self.name(),
+                                name
+                            ))
+                            .await;
+                            exit_code = 1;
+                        }
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Hash(args) => {
+                if args.is_empty() {
+                    let entries = shell.hash_table.entries();
+                    if entries.is_empty() {
+                        out.writeln(format!("{}: hash table empty", self.name()))
+                            .await;
+                        return RunResult::Code(0);
+                    }
+                    let mut lines = "hits\tcommand\n".to_string();
+                    for (_, path, hits) in entries {
+                        lines += &format!("{:>4}\t{}\n", hits, path.display());
+                    }
+                    out.write(lines).await;
+                    return RunResult::Code(0);
+                }
+
+                let mut args = args.as_slice();
+                if args[0] == "-r" {
+                    shell.hash_table.clear();
+                    args = &args[1..];
+                }
+                // -p gives the path to use instead of searching for it
+                let mut path = None;
+                if args.first().map(|arg| arg == "-p").unwrap_or(false) {
+                    if args.len() < 3 {
+                        err.writeln(format!(
+                            "{}: usage: hash [-r] [-p pathname] [name ...]",
+                            self.name()
+                        ))
+                        .await;
+                        return RunResult::Code(2);
+                    }
+                    path = Some(PathBuf::from(&args[1]));
+                    args = &args[2..];
+                }
+
+                let mut exit_code = 0;
+                for name in args {
+                    // builtins don't need finding. Checking looks the name up, but adding it resets the hits
+                    if path.is_none()
+                        && parser::parse_command(&vec![name.clone()], shell)
+                            .is_some_and(|command| command.is_builtin())
+                    {
+                        continue;
+                    }
+                    if !shell.hash_table.add(name, path.clone()) {
+                        err.writeln(format!("{}: {}: not found", self.name(), name))
+                            .await;
+                        exit_code = 1;
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::CommandBuiltin(args) => {
+                let verbose = match args.first().map(|arg| arg.as_str()) {
+                    Some("-v") => false,
+                    Some("-V") => true,
+                    None => return RunResult::Code(0),
+                    // the words were expanded already, and an alias is only expanded where a command starts, so this
+                    // is never an alias
+                    Some(_) => {
+                        let command = parser::parse_command(args, shell).unwrap();
+                        return Box::pin(command.run_with_io(shell, iin, out, err)).await;
+                    }
+                };
+
+                // -v prints how the name would be run, -V describes it like type
+                let mut exit_code = 0;
+                for name in &args[1..] {
+                    let command = parser::parse_command(&vec![name.clone()], shell).unwrap();
+                    if let Some(alias) = shell.aliases.get(name) {
+                        match verbose {
+                            true => out.writeln(format!("{} is aliased to '{}'", name, alias)),
+                            false => out.writeln(format_alias(name, alias)),
+                        }
+                        .await;
+                        continue;
+                    }
+                    match (&command, verbose) {
+                        (Command::InvalidCommand(..), true) => {
+                            err.writeln(format!("{}: {}: not found", self.name(), name))
+                                .await;
+                            exit_code = 1;
+                        }
+                        (Command::InvalidCommand(..), false) => exit_code = 1,
+                        (Command::Executable(_, path, _), false) => {
+                            out.writeln(path.display().to_string()).await
+                        }
+                        (_, false) => out.writeln(name.clone()).await,
+                        (_, true) => out.writeln(command.r#type()).await,
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Trap(args) => {
+                let mut args = args.as_slice();
+                match args.first().map(|arg| arg.as_str()) {
+                    Some("-l") => {
+                        out.writeln(signal::names().join(" ")).await;
+                        return RunResult::Code(0);
+                    }
+                    None | Some("-p") => {
+                        for (signal, action) in &shell.traps {
+                            out.writeln(format!("trap -- {} {}", quote(action), signal))
+                                .await;
+                        }
+                        return RunResult::Code(0);
+                    }
+                    Some("--") => args = &args[1..],
+                    _ => (),
+                }
+                // `trap - INT` (or just `trap INT`) puts the signals back, otherwise the first word is the command
+                let (action, signals) = match args {
+                    [first, rest @ ..] if first == "-" => (None, rest),
+                    [_] => (None, args),
+                    [first, rest @ ..] => (Some(first.clone()), rest),
+                    [] => return RunResult::Code(0),
+                };
+                let mut exit_code = 0;
+                for spec in signals {
+                    let name = match spec.to_ascii_uppercase().as_str() {
+                        "EXIT" | "0" => "EXIT",
+                        _ => match signal::parse(spec) {
+                            Some(libc::SIGINT) => "SIGINT",
+                            Some(libc::SIGTERM) => "SIGTERM",
+                            Some(libc::SIGHUP) => "SIGHUP",
+                            Some(_) => {
+                                err.writeln(format!(
+                                    "{}: {}: only INT, TERM, HUP, and EXIT can be trapped",
+                                    self.name(),
+                                    spec
+                                ))
+                                .await;
+                                exit_code = 1;
+                                continue;
+                            }
+                            None => {
+                                err.writeln(format!(
+                                    "{}: {}: invalid signal specification",
+                                    self.name(),
+                                    spec
+                                ))
+                                .await;
+                                exit_code = 1;
+                                continue;
+                            }
+                        },
+                    };
+                    match &action {
+                        Some(action) => shell.traps.insert(name.to_string(), action.clone()),
+                        None => shell.traps.remove(name),
+                    };
+                    match name {
+                        "SIGINT" => signal::trap_interrupt(action.as_deref()),
+                        _ => shell.trap_terminations(),
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Timeout(args) => {
+                let (duration, command) = match args.split_first() {
+                    Some((duration, command)) if !command.is_empty() => (duration, command),
+                    _ => {
+                        err.writeln(format!(
+                            "{}: usage: timeout duration command [arg ...]",
+                            self.name()
+                        ))
+                        .await;
+                        return RunResult::Code(2);
+                    }
+                };
+                let duration = match parse_duration(duration) {
+                    Some(duration) => duration,
+                    None => {
+                        err.writeln(format!(
+                            "{}: {}: invalid time interval",
+                            self.name(),
+                            duration
+                        ))
+                        .await;
+                        return RunResult::Code(125);
+                    }
+                };
+                // 127 when there's nothing by that name and 125 when it can't be run, like coreutils' timeout
+                let command = match parser::parse_command(&command.to_vec(), shell) {
+                    Some(Command::InvalidCommand(name)) => {
+                        err.writeln(format!("{}: {}: not found", self.name(), name.trim()))
+                            .await;
+                        return RunResult::Code(127);
+                    }
+                    Some(command) => command,
+                    None => {
+                        err.writeln(format!("{}: failed to run command", self.name()))
+                            .await;
+                        return RunResult::Code(125);
+                    }
+                };
+                let mut result = Box::pin(command.run_with_io(shell, iin, out, err)).await;
+                // builtins have already finished by now, only a program can still be running. A duration of 0 never
+                // times out
+                let child = match &mut result {
+                    RunResult::Child(child) if !duration.is_zero() => child,
+                    _ => return result,
+                };
+                if tokio::time::timeout(duration, child.wait()).await.is_ok() {
+                    return result;
+                }
+                // asked to stop first, so it can clean up, and only killed if it's still going after that
+                if let Some(pid) = child.id() {
+                    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+                }
+                if tokio::time::timeout(TIMEOUT_GRACE, child.wait())
+                    .await
+                    .is_err()
+                {
+                    let _ = child.kill().await;
+                }
+                // like coreutils' timeout, so scripts can tell it apart from the command failing
+                return RunResult::Code(124);
+            }
+            Command::Which(args) => {
+                let mut args = args.as_slice();
+                let all = args.first().map(|arg| arg == "-a").unwrap_or(false);
+                if all {
+                    args = &args[1..];
+                }
+
+                // only programs, not builtins or aliases, and just their paths so the output can be used directly
+                let mut exit_code = 0;
+                for name in args {
+                    let paths = match name.contains('/') {
+                        true if parser::is_executable(Path::new(name)) => vec![PathBuf::from(name)],
+                        true => Vec::new(),
+                        false => parser::find_executables(name),
+                    };
+                    if paths.is_empty() {
+                        err.writeln(format!("{} not found", name)).await;
+                        exit_code = 1;
+                    }
+                    let count = if all { paths.len() } else { 1 };
+                    for path in paths.iter().take(count) {
+                        out.writeln(path.display().to_string()).await;
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Background(command, text) if !command.is_simple() => {
+                // a pipeline or compound command has to keep going while the prompt comes back, so like other shells
+                // it runs in a copy of the shell
+                let mut pcommand = tokio::process::Command::new(
+                    env::current_exe().unwrap_or_else(|_| PathBuf::from(&shell.shell_name)),
+                );
                 pcommand
+                    .arg("-c")
+                    .arg(shell_state(shell) + text)
+                    .arg(&shell.shell_name)
+                    .args(&shell.positional_parameters)
+                    .stdin(iin.as_stdin())
+                    .stdout(out.as_stdio())
+                    .stderr(err.as_stdio())
+                    .process_group(0);
+                unsafe {
+                    pcommand.pre_exec(|| {
+                        signal::reset_for_child();
+                        return Ok(());
+                    })
+                };
+                match pcommand.spawn() {
+                    Ok(child) => {
+                        let pid = child.id().unwrap_or_default();
+                        let number = shell.jobs.add(child, text.clone());
+                        err.writeln(format!("[{}] {}", number, pid)).await;
+                        return RunResult::Code(0);
+                    }
+                    Err(error) => {
+                        err.writeln(format!("{}: {}", shell.shell_name, error))
+                            .await;
+                        return RunResult::Code(1);
+                    }
+                }
+            }
+            Command::Background(command, text) => {
+                let background = std::mem::replace(&mut shell.background, true);
+                let mut result = Box::pin(command.run_with_io(shell, iin, out, err.clone())).await;
+                shell.background = background;
+                match result {
+                    RunResult::Child(child) => {
+                        let pid = child.id().unwrap_or_default();
+                        let number = shell.jobs.add(child, text.clone());
+                        err.writeln(format!("[{}] {}", number, pid)).await;
+                    }
+                    // builtins have already finished by the time they return
+                    _ => {
+                        result.wait().await;
+                    }
+                }
+                return RunResult::Code(0);
+            }
+            Command::Executable(_, path, args) => {
+                // the path that was found, so it's the same program `type` shows, with the name it was run by as $0
+                let mut pcommand = tokio::process::Command::new(path);
+                pcommand
+                    .arg0(self.name())
                     .args(args)
                     .stdin(iin.as_stdin())
                     .stdout(out.as_stdio())
                     .stderr(err.as_stdio());
-                let child = pcommand.spawn().unwrap();
-                return RunResult::Child(child);
+                // copies of the other redirected descriptors, above where they go so moving one into place can't
+                // overwrite another before it's been moved. They're closed in the child once it starts the program
+                let fds: Vec<(OwnedFd, RawFd)> = shell
+                    .redirected_fds
+                    .iter()
+                    .filter_map(|(fd, io)| {
+                        let copy =
+                            unsafe { libc::fcntl(io.as_raw_fd()?, libc::F_DUPFD_CLOEXEC, 10) };
+                        if copy < 0 {
+                            return None;
+                        }
+                        return Some((unsafe { OwnedFd::from_raw_fd(copy) }, *fd));
+                    })
+                    .collect();
+                // only signal and dup2 run in the child, which are safe to call between fork and exec
+                unsafe {
+                    pcommand.pre_exec(move || {
+                        signal::reset_for_child();
+                        for (source, target) in &fds {
+                            if libc::dup2(source.as_raw_fd(), *target) < 0 {
+                                return Err(std::io::Error::last_os_error());
+                            }
+                        }
+                        return Ok(());
+                    })
+                };
+                if shell.background {
+                    pcommand.process_group(0);
+                }
+                // the file can change between finding it and running it, like being deleted or losing its execute bit
+                return match pcommand.spawn() {
+                    Ok(child) => RunResult::Child(child),
+                    Err(error) => {
+                        let (reason, exit_code) = match error.kind() {
+                            _ if path.is_dir() => ("Is a directory".to_string(), 126),
+                            ErrorKind::NotFound => ("No such file or directory".to_string(), 127),
+                            ErrorKind::PermissionDenied => ("Permission denied".to_string(), 126),
+                            _ => (error.to_string(), 126),
+                        };
+                        err.writeln(format!("{}: {}", self.name(), reason)).await;
+                        RunResult::Code(exit_code)
+                    }
+                };
             }
             Command::InvalidCommand(input) => {
-                err.writeln(format!("{}: command not found", input.trim()))
-                    .await;
+                // a path isn't searched for, so it's just missing
+                let reason = match input.contains('/') {
+                    true => "No such file or directory",
+                    false => "command not found",
+                };
+                err.writeln(format!("{}: {}", input.trim(), reason)).await;
+                return RunResult::Code(127);
             }
-            Command::Pipe(left_command, right_command) => {
-                let (sender, receiver) = tokio::net::unix::pipe::pipe().unwrap();
-                let out_pipe = IO::Pipe(Some(sender), None);
-                let in_pipe = IO::Pipe(None, Some(receiver));
-                let mut left_child =
-                    Box::pin(left_command.run_with_io(iin, out_pipe, err.clone())).await;
-                let mut right_child = Box::pin(right_command.run_with_io(in_pipe, out, err)).await;
-
-                // important to spawn the children before awaiting to avoid blocking the data passing through the pipe
-                left_child.wait().await;
-                right_child.wait().await;
-            }
-            Command::Redirect(out_path, err_path, command) => {
-                let out = out_path.as_io();
-                let err = err_path.as_io();
-                Box::pin(command.run_with_io(iin, out, err))
-                    .await
-                    .wait()
+            Command::Pipe(..) => {
+                let stages = self.pipeline_stages();
+                // expanded up front so it's known which stages are programs and which are builtins
+                let mut commands = Vec::new();
+                let mut stage_assignments = Vec::new();
+                for stage in &stages {
+                    let (assignments, command) = match stage {
+                        Command::Simple(words) => {
+                            match Command::expand_simple(words, shell, &mut err).await {
+                                Ok((assignments, command)) => {
+                                    (assignments, Some(command.unwrap_or(Command::True)))
+                                }
+                                Err(()) => (Vec::new(), Some(Command::False)),
+                            }
+                        }
+                        _ => (Vec::new(), None),
+                    };
+                    commands.push(command);
+                    stage_assignments.push(assignments);
+                }
+
+                // programs start first, from the right, so something is already reading whenever a builtin writes more
+                // than the pipe holds. builtins then run from the left, so one that reads (like read) gets what the
+                // builtin before it wrote
+                let is_program = |i: &usize| matches!(commands[*i], Some(Command::Executable(..)));
+
+                // each stage reads from the pipe before it and writes to the pipe after it. Between two builtins the
+                // left one finishes before the right one starts reading, so that pipe can't ever fill up
+                let mut ins = vec![Some(iin)];
+                let mut outs = Vec::new();
+                for i in 1..stages.len() {
+                    let (stage_out, stage_in) = match is_program(&(i - 1)) || is_program(&i) {
+                        true => {
+                            let (sender, receiver) = tokio::net::unix::pipe::pipe().unwrap();
+                            (IO::Pipe(Some(sender), None), IO::Pipe(None, Some(receiver)))
+                        }
+                        false => IO::unbounded_pipe(),
+                    };
+                    outs.push(Some(stage_out));
+                    ins.push(Some(stage_in));
+                }
+                outs.push(Some(out));
+                let mut order: Vec<usize> = (0..stages.len()).rev().filter(is_program).collect();
+                order.extend((0..stages.len()).filter(|i| !is_program(i)));
+
+                let mut results: Vec<Option<RunResult>> = stages.iter().map(|_| None).collect();
+                for i in order {
+                    let stage = commands[i].as_ref().unwrap_or(stages[i]);
+                    // the stage takes its ends of the pipes, so once it's done with them they're closed and the next
+                    // stage sees the end of its input. A builtin drops them when it returns, a program's copies are
+                    // closed in the shell as soon as it's spawned
+                    let (stage_in, stage_out) = (ins[i].take().unwrap(), outs[i].take().unwrap());
+                    let previous = set_variables(&stage_assignments[i], shell);
+                    results[i] = Some(
+                        Box::pin(stage.run_with_io(shell, stage_in, stage_out, err.clone())).await,
+                    );
+                    restore_variables(previous, shell);
+                }
+                // every stage is running before any are waited on, so data flows through the whole pipeline at once
+                let mut exit_codes = Vec::new();
+                for result in &mut results {
+                    exit_codes.push(result.as_mut().unwrap().wait().await);
+                }
+                return RunResult::Code(pipeline_status(&exit_codes, shell.options.pipefail));
+            }
+            Command::Redirect(redirects, command) => {
+                // the redirects change the descriptors open so far one at a time, so `> file 2>&1` sends both streams
+                // to the file but `2>&1 > file` only sends stdout there. Every file is opened even when a later redirect
+                // replaces it, so `> a > b` still creates (or empties) a and the output goes to b
+                let mut fds = shell.redirected_fds.clone();
+                fds.insert(0, iin);
+                fds.insert(1, out);
+                fds.insert(2, err.clone());
+                for (fd, redirect) in redirects {
+                    match redirect.as_io(&fds, shell).await {
+                        Ok(io) => fds.insert(*fd, io),
+                        // a redirect that can't be opened stops the command from running
+                        Err(error) => {
+                            err.writeln(error).await;
+                            return RunResult::Code(1);
+                        }
+                    };
+                }
+                let (iin, out, err) = (
+                    fds.remove(&0).unwrap(),
+                    fds.remove(&1).unwrap(),
+                    fds.remove(&2).unwrap(),
+                );
+
+                // the rest are only for programs, which get them when they start
+                let previous = std::mem::replace(&mut shell.redirected_fds, fds);
+                let mut result = Box::pin(command.run_with_io(shell, iin, out, err)).await;
+                let exit_code = result.wait().await;
+                shell.redirected_fds = previous;
+                if let RunResult::Exit(..) = result {
+                    return result;
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Subshell(command) => {
+                let snapshot = shell.snapshot();
+                let mut result = Box::pin(command.run_with_io(shell, iin, out, err)).await;
+                let exit_code = result.wait().await;
+                shell.restore(snapshot);
+                // exit only leaves the subshell
+                return RunResult::Code(exit_code);
+            }
+            Command::Group(command) => {
+                return Box::pin(command.run_with_io(shell, iin, out, err)).await;
+            }
+            Command::Time(command) => {
+                let start = Instant::now();
+                let (user_start, sys_start) = cpu_times();
+                let mut result = Box::pin(command.run_with_io(shell, iin, out, err.clone())).await;
+                let exit_code = result.wait().await;
+                let (user_end, sys_end) = cpu_times();
+                err.write(format!(
+                    "\nreal\t{}\nuser\t{}\nsys\t{}\n",
+                    format_duration(start.elapsed()),
+                    format_duration(user_end.saturating_sub(user_start)),
+                    format_duration(sys_end.saturating_sub(sys_start))
+                ))
+                .await;
+                return match result {
+                    RunResult::Child(..) => RunResult::Code(exit_code),
+                    _ => result,
+                };
+            }
+            Command::If(condition, then_command, else_command) => {
+                let mut condition_result =
+                    Box::pin(condition.run_with_io(shell, iin.clone(), out.clone(), err.clone()))
+                        .await;
+                // like the left side of && and ||, set -e doesn't apply to the condition
+                let exit_code = condition_result.wait().await;
+                shell.last_status = exit_code;
+                if let RunResult::Exit(..) = condition_result {
+                    return condition_result;
+                }
+
+                if exit_code == 0 {
+                    return Box::pin(then_command.run_with_io(shell, iin, out, err)).await;
+                }
+                if let Some(else_command) = else_command {
+                    return Box::pin(else_command.run_with_io(shell, iin, out, err)).await;
+                }
+                // no branch ran, which isn't a failure
+                return RunResult::Code(0);
+            }
+            Command::While(until, condition, body) => {
+                // the status of the last time the body ran, or 0 if it never did
+                let mut exit_code = 0;
+                loop {
+                    let mut condition_result = Box::pin(condition.run_with_io(
+                        shell,
+                        iin.clone(),
+                        out.clone(),
+                        err.clone(),
+                    ))
                     .await;
+                    let condition_code = condition_result.wait().await;
+                    shell.last_status = condition_code;
+                    if let RunResult::Exit(..) = condition_result {
+                        return condition_result;
+                    }
+                    // Ctrl-C stops the loop, even when everything in it is a builtin, unless it's trapped
+                    if let Some(exit_code) = handle_signals(shell).await {
+                        return RunResult::Exit(exit_code);
+                    }
+                    if signal::interrupted() {
+                        return RunResult::Code(condition_code);
+                    }
+                    if (condition_code == 0) == *until {
+                        break;
+                    }
+
+                    let mut body_result =
+                        Box::pin(body.run_with_io(shell, iin.clone(), out.clone(), err.clone()))
+                            .await;
+                    exit_code = body_result.wait_errexit(shell).await;
+                    if let RunResult::Exit(..) = body_result {
+                        return body_result;
+                    }
+                    if let Some(exit_code) = handle_signals(shell).await {
+                        return RunResult::Exit(exit_code);
+                    }
+                    if signal::interrupted() {
+                        break;
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::Case(word, clauses) => {
+                let word = expand::expand_value(word, shell).await;
+                for (patterns, body) in clauses {
+                    let mut matched = false;
+                    for pattern in patterns {
+                        if glob::matches(&expand::expand_pattern(pattern, shell).await, &word) {
+                            matched = true;
+                            break;
+                        }
+                    }
+                    if !matched {
+                        continue;
+                    }
+                    return match body {
+                        Some(body) => Box::pin(body.run_with_io(shell, iin, out, err)).await,
+                        None => RunResult::Code(0),
+                    };
+                }
+                // no clause matching isn't a failure
+                return RunResult::Code(0);
+            }
+            Command::For(name, words, body) => {
+                let mut exit_code = 0;
+                for word in expand::expand_words(words, shell).await {
+                    shell.set_var(name, &word);
+                    let mut body_result =
+                        Box::pin(body.run_with_io(shell, iin.clone(), out.clone(), err.clone()))
+                            .await;
+                    exit_code = body_result.wait_errexit(shell).await;
+                    if let RunResult::Exit(..) = body_result {
+                        return body_result;
+                    }
+                    if let Some(exit_code) = handle_signals(shell).await {
+                        return RunResult::Exit(exit_code);
+                    }
+                    if signal::interrupted() {
+                        break;
+                    }
+                }
+                return RunResult::Code(exit_code);
+            }
+            Command::And(left_command, right_command)
+            | Command::Or(left_command, right_command)
+            | Command::Sequence(left_command, right_command) => {
+                let mut left_result = Box::pin(left_command.run_with_io(
+                    shell,
+                    iin.clone(),
+                    out.clone(),
+                    err.clone(),
+                ))
+                .await;
+                // set -e doesn't apply to the left side of && and ||, since failing there is how they choose what to do
+                let exit_code = match self {
+                    Command::Sequence(..) => left_result.wait_errexit(shell).await,
+                    _ => left_result.wait().await,
+                };
+                shell.last_status = exit_code;
+                if let RunResult::Exit(..) = left_result {
+                    return left_result;
+                }
+
+                // Ctrl-C gives up on the rest of the line, not just the command that was running, unless it's trapped
+                if let Some(exit_code) = handle_signals(shell).await {
+                    return RunResult::Exit(exit_code);
+                }
+                let run_right = match self {
+                    _ if signal::interrupted() => false,
+                    Command::And(..) => exit_code == 0,
+                    Command::Or(..) => exit_code != 0,
+                    _ => true,
+                };
+                if !run_right {
+                    return RunResult::Exempt(exit_code);
+                }
+                return Box::pin(right_command.run_with_io(shell, iin, out, err)).await;
+            }
+            Command::Simple(words) => {
+                let (assignments, command) =
+                    match Command::expand_simple(words, shell, &mut err).await {
+                        Ok(expanded) => expanded,
+                        Err(()) => return RunResult::Code(1),
+                    };
+                // assignments on their own are for the shell, otherwise they're only for the command
+                if command.is_none() {
+                    for (name, value) in assignments {
+                        shell.set_var(&name, &value);
+                    }
+                    return RunResult::Code(0);
+                }
+                let previous = set_variables(&assignments, shell);
+                let result = Box::pin(command.unwrap().run_with_io(shell, iin, out, err)).await;
+                restore_variables(previous, shell);
+                return result;
             }
         }
         return RunResult::None;
     }
 
     fn r#type(&self) -> String {
+        if self.is_builtin() {
+            return format!("{} is a shell builtin", self.name());
+        }
         return match self {
-            Command::Echo(..)
-            | Command::Exit
-            | Command::Type(..)
-            | Command::PWD
-            | Command::CD(..) => {
-                format!("{} is a shell builtin", self.name())
-            }
-            Command::Executable(path, _) => format!("{} is {}", self.name(), path.display()),
+            Command::Executable(_, path, _) => format!("{} is {}", self.name(), path.display()),
             Command::InvalidCommand(input) => format!("{}: not found", input.trim()),
             _ => panic!("Invalid command for type!"),
         };
     }
 
+    /** Expands a simple command's leading `NAME=value` assignments and its words, and finds the command they name. The
+    command is None if the words expand to nothing */
+    async fn expand_simple(
+        words: &[String],
+        shell: &mut Shell,
+        err: &mut IO,
+    ) -> Result<(Vec<(String, String)>, Option<Command>), ()> {
+        let mut words = words;
+        let mut assignments = Vec::new();
+        while let Some((name, value)) = words
+            .first()
+            .and_then(|word| parser::split_assignment(word))
+        {
+            let value = expand::expand_value(value, shell).await;
+            // a bad assignment stops the command from running, the error is shown here
+            match assignment_value(name, &value, shell) {
+                Ok(value) => assignments.push((name.to_string(), value)),
+                Err(error) => {
+                    err.writeln(error).await;
+                    return Err(());
+                }
+            }
+            words = &words[1..];
+        }
+        let words = expand::expand_words(words, shell).await;
+
+        if shell.options.xtrace && (!assignments.is_empty() || !words.is_empty()) {
+            let mut trace: Vec<String> = assignments
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            trace.extend(words.iter().cloned());
+            err.writeln(format!("+ {}", trace.join(" "))).await;
+        }
+        return Ok((assignments, parser::parse_command(&words, shell)));
+    }
+
+    /** The commands of a pipeline from left to right, since `a | b | c` is parsed as `(a | b) | c` */
+    fn pipeline_stages(&self) -> Vec<&Command> {
+        return match self {
+            Command::Pipe(left_command, right_command) => {
+                let mut stages = left_command.pipeline_stages();
+                stages.push(right_command);
+                stages
+            }
+            _ => vec![self],
+        };
+    }
+
+    /** `type -a`, every builtin and PATH match for the name instead of just the one that would run */
+    fn type_all(&self) -> Vec<String> {
+        let name = self.lookup_name();
+
+        let mut types = Vec::new();
+        if self.is_builtin() {
+            types.push(self.r#type());
+        }
+        for path in parser::find_executables(name) {
+            types.push(format!("{} is {}", name, path.display()));
+        }
+        if types.is_empty() {
+            types.push(self.r#type());
+        }
+        return types;
+    }
+
+    /** The name that was looked up to find this command, even if nothing was found */
+    fn lookup_name(&self) -> &str {
+        return match self {
+            Command::InvalidCommand(input) => input.trim(),
+            _ => self.name(),
+        };
+    }
+
+    /** Whether it's one of the builtins in the builtins table */
+    fn is_builtin(&self) -> bool {
+        return match self {
+            Command::Executable(..) | Command::InvalidCommand(..) => false,
+            command => builtins::find(command.name()).is_some(),
+        };
+    }
+
+    /** A single command, maybe with redirects, which is started and left running without needing the shell */
+    fn is_simple(&self) -> bool {
+        return match self {
+            Command::Simple(..) => true,
+            Command::Redirect(_, command) => command.is_simple(),
+            _ => false,
+        };
+    }
+
     fn name(&self) -> &str {
         return match self {
-            Command::Exit => "exit",
+            Command::Exit(..) => "exit",
             Command::Echo(..) => "echo",
             Command::Type(..) => "type",
-            Command::PWD => "pwd",
+            Command::PWD(..) => "pwd",
             Command::CD(..) => "cd",
-            Command::Executable(path, _) => path.file_name().unwrap().to_str().unwrap(),
+            Command::History(..) => "history",
+            Command::Printf(..) => "printf",
+            Command::Source(..) => "source",
+            Command::Set(..) => "set",
+            Command::Shift(..) => "shift",
+            Command::Eval(..) => "eval",
+            Command::Alias(..) => "alias",
+            Command::Unalias(..) => "unalias",
+            Command::True => "true",
+            Command::False => "false",
+            Command::Test(true, _) => "[",
+            Command::Test(false, _) => "test",
+            Command::Kill(..) => "kill",
+            Command::Jobs(..) => "jobs",
+            Command::Fg(..) => "fg",
+            Command::Bg(..) => "bg",
+            Command::Read(..) => "read",
+            Command::Umask(..) => "umask",
+            Command::Pushd(..) => "pushd",
+            Command::Popd(..) => "popd",
+            Command::Dirs(..) => "dirs",
+            Command::Env => "env",
+            Command::Printenv(..) => "printenv",
+            Command::Hash(..) => "hash",
+            Command::CommandBuiltin(..) => "command",
+            Command::Which(..) => "which",
+            Command::Declare(..) => "declare",
+            Command::Export(..) => "export",
+            Command::Unset(..) => "unset",
+            Command::Help(..) => "help",
+            Command::Timeout(..) => "timeout",
+            Command::Trap(..) => "trap",
+            Command::Executable(name, ..) => name,
             Command::InvalidCommand(..) => "invalid_command",
             _ => panic!("Invalid command for name!"),
         };
     }
 }
 
+/** A pipeline's exit code is its last command's, so `false | true` succeeds. With pipefail it's the last one to fail */
+fn pipeline_status(exit_codes: &[i32], pipefail: bool) -> i32 {
+    if pipefail {
+        return exit_codes
+            .iter()
+            .rev()
+            .find(|exit_code| **exit_code != 0)
+            .copied()
+            .unwrap_or(0);
+    }
+    return *exit_codes.last().unwrap();
+}
+
+/** The user and system CPU time used so far by the shell and the children it has waited for, so the difference
+between two calls covers both builtins and programs */
+fn cpu_times() -> (Duration, Duration) {
+    let mut user = Duration::ZERO;
+    let mut sys = Duration::ZERO;
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+            continue;
+        }
+        user += timeval_duration(usage.ru_utime);
+        sys += timeval_duration(usage.ru_stime);
+    }
+    return (user, sys);
+}
+
+fn timeval_duration(time: libc::timeval) -> Duration {
+    return Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64);
+}
+
+/** A `timeout` duration, in seconds unless it ends in m, h, or d, like `1.5` or `2m` */
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.strip_suffix(['s', 'm', 'h', 'd']) {
+        Some(number) => (number, &text[number.len()..]),
+        None => (text, "s"),
+    };
+    let multiplier = match unit {
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        "d" => 24.0 * 60.0 * 60.0,
+        _ => 1.0,
+    };
+    let seconds = number.parse::<f64>().ok()? * multiplier;
+    return Duration::try_from_secs_f64(seconds).ok();
+}
+
+/** A duration as minutes and seconds to the millisecond, like `0m1.003s` */
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    return format!(
+        "{}m{}.{:03}s",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    );
+}
+
+/** Permission bits as `u=rwx,g=rx,o=rx`, like `umask -S` */
+fn symbolic_mode(mode: libc::mode_t) -> String {
+    let mut classes = Vec::new();
+    for (class, shift) in [("u", 6), ("g", 3), ("o", 0)] {
+        let mut permissions = String::new();
+        for (permission, bit) in [('r', 0o4), ('w', 0o2), ('x', 0o1)] {
+            if mode >> shift & bit != 0 {
+                permissions.push(permission);
+            }
+        }
+        classes.push(format!("{}={}", class, permissions));
+    }
+    return classes.join(",");
+}
+
+/** Resolves `.` and `..` in the path without looking at the filesystem, so `..` goes back out of a symlink instead
+of to the symlink target's parent */
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    return normalized;
+}
+
+/** Changes to the directory, resolving `.` and `..` against the directory as the user got to it (through symlinks).
+Keeps PWD and OLDPWD up to date, and returns the new directory. The error is why the directory couldn't be entered */
+fn change_dir(path: &str) -> Result<PathBuf, String> {
+    // the directory as the user got to it, so cd - goes back through the same symlinks
+    let previous_dir = shell::working_dir();
+    let target = normalize(&previous_dir.join(path));
+
+    // check each component in turn, so a file partway through is reported as that and not as missing
+    let mut checked = PathBuf::new();
+    for component in target.components() {
+        checked.push(component);
+        match fs::metadata(&checked) {
+            Ok(metadata) if metadata.is_dir() => (),
+            Ok(_) => return Err("Not a directory".to_string()),
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err("No such file or directory".to_string())
+            }
+            Err(error) => return Err(error.to_string()),
+        }
+    }
+
+    if let Err(error) = env::set_current_dir(&target) {
+        return Err(match error.raw_os_error() {
+            Some(libc::EACCES) => "Permission denied".to_string(),
+            _ => error.to_string(),
+        });
+    }
+    // stored through symlinks if that still names where cd ended up
+    env::set_var("PWD", &target);
+    let current_dir = shell::working_dir();
+    env::set_var("OLDPWD", previous_dir);
+    env::set_var("PWD", &current_dir);
+    return Ok(current_dir);
+}
+
+/** The current directory then the stack, most recent first, like `dirs` prints it. Directories under HOME start with `~` */
+fn format_directory_stack(shell: &Shell) -> String {
+    let current_dir = shell::working_dir();
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let mut dirs = Vec::new();
+    for dir in std::iter::once(&current_dir).chain(shell.directory_stack.iter().rev()) {
+        let dir = match home.as_ref().and_then(|home| dir.strip_prefix(home).ok()) {
+            Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+            Some(rest) => format!("~/{}", rest.display()),
+            None => dir.display().to_string(),
+        };
+        dirs.push(dir);
+    }
+    return dirs.join(" ");
+}
+
+/** The value to store for an assignment, which for an integer variable is the value evaluated as arithmetic, so after
+`declare -i n=5`, `n=n+2` sets n to 7. The error is the message to show */
+fn assignment_value(name: &str, value: &str, shell: &Shell) -> Result<String, String> {
+    if !shell.integer_variables.contains(name) {
+        return Ok(value.to_string());
+    }
+    return arithmetic::evaluate(value, shell).map(|value| value.to_string());
+}
+
+/** Sets variables for just one command, returning what they were before so they can be put back afterwards. They're
+exported for the command, even ones that are otherwise the shell's own */
+fn set_variables(
+    assignments: &[(String, String)],
+    shell: &mut Shell,
+) -> Vec<(String, Option<String>, Option<OsString>)> {
+    let mut previous = Vec::new();
+    for (name, value) in assignments {
+        previous.push((
+            name.clone(),
+            shell.variables.remove(name),
+            env::var_os(name),
+        ));
+        env::set_var(name, value);
+    }
+    return previous;
+}
+
+fn restore_variables(previous: Vec<(String, Option<String>, Option<OsString>)>, shell: &mut Shell) {
+    // in reverse, so a name assigned twice ends up with its original value
+    for (name, shell_value, value) in previous.into_iter().rev() {
+        match value {
+            Some(value) => env::set_var(&name, value),
+            None => env::remove_var(&name),
+        }
+        if let Some(shell_value) = shell_value {
+            shell.variables.insert(name, shell_value);
+        }
+    }
+}
+
+/** Runs the text a line at a time, like a file being sourced. Each line runs in this shell, so cd and variables stick
+around afterwards. Finishes with the status of the last command */
+async fn run_lines(text: &str, shell: &mut Shell, iin: IO, out: IO, err: IO) -> RunResult {
+    let mut exit_code = 0;
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // heredoc bodies come from the lines that follow
+        let mut input = line.to_string();
+        while parser::needs_more_lines(&input) {
+            match lines.next() {
+                Some(line) => input += &format!("\n{}", line),
+                None => break,
+            }
+        }
+        let command = parser::parse_input(&input, shell);
+        if command.is_none() {
+            continue;
+        }
+        let mut result = Box::pin(command.unwrap().run_with_io(
+            shell,
+            iin.clone(),
+            out.clone(),
+            err.clone(),
+        ))
+        .await;
+        exit_code = result.wait_errexit(shell).await;
+        if let RunResult::Exit(..) = result {
+            return result;
+        }
+        if let Some(exit_code) = handle_signals(shell).await {
+            return RunResult::Exit(exit_code);
+        }
+        if signal::interrupted() {
+            break;
+        }
+    }
+    return RunResult::Code(exit_code);
+}
+
+/** Runs the command `trap` set for a signal (like `SIGINT` or `EXIT`), leaving `$?` as it was. Returns the exit code if the
+trap exits the shell */
+pub async fn run_trap(signal: &str, shell: &mut Shell) -> Option<i32> {
+    let action = shell.traps.get(signal)?.clone();
+    let last_status = shell.last_status;
+    let mut result = Box::pin(run_lines(
+        &action,
+        shell,
+        IO::Default,
+        IO::Default,
+        IO::Stderr,
+    ))
+    .await;
+    result.wait().await;
+    shell.last_status = last_status;
+    if let RunResult::Exit(exit_code) = result {
+        return Some(exit_code);
+    }
+    return None;
+}
+
+/** Handles the signals that came in while a command ran. A trapped one runs its trap, and the shell carries on as if it
+hadn't come. SIGTERM or SIGHUP that's only caught for the EXIT trap exits with 128 plus the signal number, so the trap
+runs on the way out. Returns the exit code if the shell should exit */
+pub async fn handle_signals(shell: &mut Shell) -> Option<i32> {
+    if let Some(signal) = signal::take_termination() {
+        let name = match signal {
+            libc::SIGHUP => "SIGHUP",
+            _ => "SIGTERM",
+        };
+        if !shell.traps.contains_key(name) {
+            return Some(128 + signal);
+        }
+        if let Some(exit_code) = run_trap(name, shell).await {
+            return Some(exit_code);
+        }
+    }
+    if !signal::interrupted() || !shell.traps.contains_key("SIGINT") {
+        return None;
+    }
+    signal::clear_interrupt();
+    return run_trap("SIGINT", shell).await;
+}
+
+/** Runs every command in the text, like `-c` does. Returns the exit code if the shell should exit */
+pub async fn run_text(text: &str, shell: &mut Shell) -> Option<i32> {
+    let mut result = run_lines(text, shell, IO::Default, IO::Default, IO::Stderr).await;
+    shell.last_status = result.wait().await;
+    if let RunResult::Exit(exit_code) = result {
+        return Some(exit_code);
+    }
+    return None;
+}
+
+/** Reads a line for `read`. From the terminal it goes through the line editor, without a prompt */
+async fn read_input_line(shell: &mut Shell, iin: &mut IO) -> Option<String> {
+    if let IO::Default = iin {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+            return shell.editor.readline("").ok();
+        }
+    }
+    return iin.read_line().await;
+}
+
+/** If the line ends with a backslash that isn't itself escaped */
+fn ends_with_escape(line: &str) -> bool {
+    let backslashes = line.chars().rev().take_while(|c| *c == '\\').count();
+    return backslashes % 2 == 1;
+}
+
+/** Commands that set up a copy of the shell like this one: the variables that aren't exported, aliases, and options.
+The copy gets the environment, current directory, and umask as a process */
+fn shell_state(shell: &Shell) -> String {
+    let mut script = String::new();
+    for name in &shell.integer_variables {
+        script += &format!("declare -i {}\n", name);
+    }
+    for (name, value) in &shell.variables {
+        script += &format!("{}={}\n", name, quote(value));
+    }
+    for (name, value) in &shell.aliases {
+        script += &format_alias(name, value);
+        script.push('\n');
+    }
+    for (name, enabled) in shell.options.all() {
+        script += &format!("set {}o {}\n", if enabled { '-' } else { '+' }, name);
+    }
+    return script;
+}
+
+/** Formats an alias the way `alias` lists it, quoted so it could be run again */
+fn format_alias(name: &str, value: &str) -> String {
+    return format!("alias {}='{}'", name, value.replace('\'', "'\\''"));
+}
+
+/** Single quotes the text if it has anything the shell would treat specially, so it can be read back in */
+fn quote(text: &str) -> String {
+    if !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,+@%=".contains(c))
+    {
+        return text.to_string();
+    }
+    return format!("'{}'", text.replace('\'', "'\\''"));
+}
+
 enum RunResult {
     None,
     Child(tokio::process::Child),
+    /** Finished with the exit code */
+    Code(i32),
+    /** Failed in a way `set -e` ignores, like the left side of `&&` */
+    Exempt(i32),
+    Exit(i32),
 }
 
 impl RunResult {
-    pub async fn wait(&mut self) {
+    /** Waits for the command to finish, returning its exit code */
+    pub async fn wait(&mut self) -> i32 {
         match self {
-            RunResult::None => {}
+            RunResult::None => 0,
+            RunResult::Code(exit_code)
+            | RunResult::Exempt(exit_code)
+            | RunResult::Exit(exit_code) => *exit_code,
             RunResult::Child(child) => {
-                child.wait().await.unwrap();
+                let status = child.wait().await.unwrap();
+                // killed by a signal there's no code, so like other shells it's 128 plus the signal number
+                status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or_default())
             }
         }
     }
 }
 
+impl RunResult {
+    /** Waits for the command to finish, keeping its status for `$?`. With `set -e` a failure becomes an exit */
+    async fn wait_errexit(&mut self, shell: &mut Shell) -> i32 {
+        let exit_code = self.wait().await;
+        shell.last_status = exit_code;
+        if shell.options.errexit
+            && exit_code != 0
+            && matches!(self, RunResult::Code(..) | RunResult::Child(..))
+        {
+            *self = RunResult::Exit(exit_code);
+        }
+        return exit_code;
+    }
+}
+
 pub enum IO {
+    /** The shell's own stdin or stdout */
     Default,
+    /** The shell's own stderr, where errors go unless they're redirected */
+    Stderr,
     File(File),
+    /** `/dev/null`, kept open so programs can be given it, but what builtins write to it is dropped without writing */
+    Null(File),
     Pipe(Option<Sender>, Option<Receiver>),
+    /** In memory, like the output of `$(...)` */
+    Buffer(Vec<u8>),
 }
 
 impl IO {
+    /** Input of the given bytes, like a heredoc's body. It goes through a pipe so everything reading it shares how far
+    it's got, like each `read` in a loop getting the next line */
+    pub fn from_bytes(bytes: Vec<u8>) -> IO {
+        let (sender, receiver) = tokio::net::unix::pipe::pipe().unwrap();
+        let mut sender = File::from(sender.into_blocking_fd().unwrap());
+        // written from another thread, since more than the pipe holds would block until it's read
+        thread::spawn(move || {
+            let _ = sender.write_all(&bytes);
+        });
+        return IO::Pipe(None, Some(receiver));
+    }
+
+    /** A pipe that holds however much is written to it until it's read, for between commands that don't run at the
+    same time. Returns the end to write to and the end to read from */
+    pub fn unbounded_pipe() -> (IO, IO) {
+        let (in_sender, in_receiver) = tokio::net::unix::pipe::pipe().unwrap();
+        let (out_sender, out_receiver) = tokio::net::unix::pipe::pipe().unwrap();
+        let mut reader = File::from(in_receiver.into_blocking_fd().unwrap());
+        let mut writer = File::from(out_sender.into_blocking_fd().unwrap());
+        // one thread keeps the first pipe empty while the other passes what's been read on as fast as it's taken
+        let (chunks_sender, chunks) = mpsc::channel::<Vec<u8>>();
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            loop {
+                match reader.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(count) => {
+                        if chunks_sender.send(chunk[..count].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        thread::spawn(move || {
+            for chunk in chunks {
+                // the reader is gone, so the rest has nowhere to go
+                if writer.write_all(&chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        return (
+            IO::Pipe(Some(in_sender), None),
+            IO::Pipe(None, Some(out_receiver)),
+        );
+    }
+
     pub async fn writeln(&mut self, data: String) {
         self.write(data + "\n").await;
     }
 
     pub async fn write(&mut self, data: String) {
         match self {
-            IO::Default => print!("{}", data),
+            // flushed straight away, so it comes out in order with what child processes write to the same terminal. A
+            // closed stdout just loses the output, where print! would panic
+            IO::Default => {
+                let mut stdout = std::io::stdout();
+                let _ = stdout.write_all(data.as_bytes());
+                let _ = stdout.flush();
+            }
+            // what builtins write to the terminal's stderr is almost always an error, so it's shown in the error color
+            IO::Stderr => {
+                let mut stderr = std::io::stderr();
+                let _ = stderr.write_all(color::error(&data).as_bytes());
+                let _ = stderr.flush();
+            }
             IO::File(file) => write!(file, "{}", data).unwrap(),
+            IO::Null(_) => (),
             IO::Pipe(sender, _) => {
-                sender.as_ref().unwrap().writable().await.unwrap();
-                sender.as_ref().unwrap().try_write(data.as_bytes()).unwrap();
+                let sender = sender.as_ref().unwrap();
+                let mut data = data.as_bytes();
+                // try_write only writes what fits in the pipe, so keep going until it's all through
+                while !data.is_empty() {
+                    sender.writable().await.unwrap();
+                    match sender.try_write(data) {
+                        Ok(count) => data = &data[count..],
+                        Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
+                        // the reader is gone (like `yes | head -1`), so the rest has nowhere to go
+                        Err(_) => break,
+                    }
+                }
+            }
+            IO::Buffer(buffer) => buffer.extend_from_slice(data.as_bytes()),
+        }
+    }
+
+    /** Appends everything from the pipe to the buffer, until the pipe is closed */
+    async fn read_from(&mut self, receiver: Receiver) {
+        let mut chunk = [0; 4096];
+        loop {
+            receiver.readable().await.unwrap();
+            match receiver.try_read(&mut chunk) {
+                Ok(0) => break,
+                Ok(count) => {
+                    if let IO::Buffer(buffer) = self {
+                        buffer.extend_from_slice(&chunk[..count]);
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
+                Err(_) => break,
+            }
+        }
+    }
+
+    /** Reads a line of input without the newline. Returns None at the end of the input */
+    pub async fn read_line(&mut self) -> Option<String> {
+        if let IO::Default = self {
+            // stdin is shared with the prompt, which reads it buffered, so it has to go through the same reader
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                return None;
+            }
+            if line.ends_with('\n') {
+                line.pop();
+            }
+            return Some(line);
+        }
+
+        // a byte at a time so nothing after the line is taken from the next reader
+        let mut line = Vec::new();
+        let mut byte = [0; 1];
+        loop {
+            let count = match self {
+                IO::Default => unreachable!(),
+                // nothing to read from
+                IO::Stderr => 0,
+                IO::File(file) | IO::Null(file) => file.read(&mut byte).unwrap_or(0),
+                IO::Pipe(_, receiver) => {
+                    let receiver = receiver.as_ref().unwrap();
+                    receiver.readable().await.unwrap();
+                    match receiver.try_read(&mut byte) {
+                        Ok(count) => count,
+                        Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
+                        Err(_) => 0,
+                    }
+                }
+                IO::Buffer(buffer) => match buffer.is_empty() {
+                    true => 0,
+                    false => {
+                        byte[0] = buffer.remove(0);
+                        1
+                    }
+                },
+            };
+            if count == 0 {
+                if line.is_empty() {
+                    return None;
+                }
+                break;
             }
+            if byte[0] == b'\n' {
+                break;
+            }
+            line.push(byte[0]);
         }
+        return Some(String::from_utf8_lossy(&line).into_owned());
+    }
+
+    /** What was written to a buffer. Other IO doesn't keep what's written to it */
+    pub fn into_bytes(self) -> Vec<u8> {
+        return match self {
+            IO::Buffer(buffer) => buffer,
+            _ => Vec::new(),
+        };
     }
 
     pub fn as_stdin(&mut self) -> Stdio {
         match self {
             IO::Default => Stdio::inherit(),
-            IO::File(file) => file.try_clone().unwrap().into(),
+            IO::Stderr => std::io::stderr().into(),
+            IO::File(file) | IO::Null(file) => file.try_clone().unwrap().into(),
             IO::Pipe(_, receiver) => receiver.take().unwrap().into_blocking_fd().unwrap().into(),
+            IO::Buffer(buffer) => IO::from_bytes(buffer.clone()).as_stdin(),
         }
     }
 
+    /** The descriptor to give a child for it, if there is one. The shell's own stdin and stdout are both `Default`,
+    which is taken as stdout since only output can be given to other descriptors */
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        return match self {
+            IO::Default => Some(libc::STDOUT_FILENO),
+            IO::Stderr => Some(libc::STDERR_FILENO),
+            IO::File(file) | IO::Null(file) => Some(file.as_raw_fd()),
+            IO::Pipe(Some(sender), _) => Some(sender.as_raw_fd()),
+            IO::Pipe(_, Some(receiver)) => Some(receiver.as_raw_fd()),
+            IO::Pipe(None, None) | IO::Buffer(..) => None,
+        };
+    }
+
     /** Used to create a output handle (out, err) */
     pub fn as_stdio(&mut self) -> Stdio {
         match self {
             IO::Default => Stdio::inherit(),
-            IO::File(file) => file.try_clone().unwrap().into(),
+            IO::Stderr => std::io::stderr().into(),
+            // the clone is the same open file, so the child and builtins writing to it share the offset (and append
+            // mode), and `{ echo a; ls; echo b; } > file` keeps everything in order
+            IO::File(file) | IO::Null(file) => file.try_clone().unwrap().into(),
             // take is really awkward, but the resulting Stdio has to be owned, and into_blocking_fd() can't be used on a reference
             IO::Pipe(sender, _) => sender.take().unwrap().into_blocking_fd().unwrap().into(),
+            IO::Buffer(..) => panic!("Can't give a buffer to a child as output"),
         }
     }
 }
@@ -204,8 +2518,360 @@ impl Clone for IO {
     fn clone(&self) -> Self {
         match self {
             Self::Default => Self::Default,
+            Self::Stderr => Self::Stderr,
             Self::File(file) => Self::File(file.try_clone().unwrap()),
-            Self::Pipe(..) => panic!("Can't clone a pipe"),
+            Self::Null(file) => Self::Null(file.try_clone().unwrap()),
+            // the copies share the pipe, so it only closes once all of them are dropped
+            Self::Pipe(sender, receiver) => Self::Pipe(
+                sender.as_ref().map(|sender| {
+                    Sender::from_owned_fd(sender.as_fd().try_clone_to_owned().unwrap()).unwrap()
+                }),
+                receiver.as_ref().map(|receiver| {
+                    Receiver::from_owned_fd(receiver.as_fd().try_clone_to_owned().unwrap()).unwrap()
+                }),
+            ),
+            Self::Buffer(buffer) => Self::Buffer(buffer.clone()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** Runs the command line, giving back what it wrote and its exit code. Fails if it takes more than a few seconds */
+    async fn capture(input: &str, shell: &mut Shell) -> (String, i32) {
+        let command = parser::parse_input(input, shell).unwrap();
+        let (output, exit_code) =
+            tokio::time::timeout(Duration::from_secs(5), command.capture(shell))
+                .await
+                .unwrap_or_else(|_| panic!("`{}` didn't finish", input));
+        return (String::from_utf8(output).unwrap(), exit_code);
+    }
+
+    #[tokio::test]
+    async fn builtin_into_a_filter_finishes() {
+        let mut shell = Shell::new();
+        assert_eq!(
+            capture("echo x | head -1", &mut shell).await,
+            ("x\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("printf 'a\\nb\\n' | cat", &mut shell).await,
+            ("a\nb\n".to_string(), 0)
+        );
+    }
+
+    /** A path in the temp directory, only used by this test run */
+    fn temp_path(name: &str) -> PathBuf {
+        return env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    }
+
+    #[tokio::test]
+    async fn append_keeps_what_was_there() {
+        let mut shell = Shell::new();
+        let log = temp_path("append");
+        capture(&format!("echo a >> {}", log.display()), &mut shell).await;
+        capture(&format!("echo b >> {}", log.display()), &mut shell).await;
+        // a builtin and a program writing to the same file, through the same redirect
+        capture(
+            &format!("{{ echo c; cat <<< d; }} >> {}", log.display()),
+            &mut shell,
+        )
+        .await;
+        assert_eq!(fs::read_to_string(&log).unwrap(), "a\nb\nc\nd\n");
+
+        capture(&format!("echo e > {}", log.display()), &mut shell).await;
+        assert_eq!(fs::read_to_string(&log).unwrap(), "e\n");
+        let _ = fs::remove_file(&log);
+    }
+
+    #[tokio::test]
+    async fn every_output_redirect_creates_its_file() {
+        let mut shell = Shell::new();
+        let (a, b) = (temp_path("redirect-a"), temp_path("redirect-b"));
+        let redirects = format!("> {} > {}", a.display(), b.display());
+        // the first is still opened, so it's created, but the output goes to the last
+        capture(&format!("echo hi {}", redirects), &mut shell).await;
+        assert_eq!(fs::read_to_string(&a).unwrap(), "");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "hi\n");
+        // and truncated if it was already there
+        fs::write(&a, "old\n").unwrap();
+        capture(&format!("echo again {}", redirects), &mut shell).await;
+        assert_eq!(fs::read_to_string(&a).unwrap(), "");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "again\n");
+        let _ = fs::remove_file(&a);
+        let _ = fs::remove_file(&b);
+    }
+
+    #[tokio::test]
+    async fn redirect_to_another_file_descriptor_creates_its_file() {
+        let mut shell = Shell::new();
+        let file = temp_path("fd-3");
+        // nothing writes to 3, so the file is only created, and stdout is left alone
+        let (output, exit_code) =
+            capture(&format!("echo hi 3>{}", file.display()), &mut shell).await;
+        assert_eq!((output.as_str(), exit_code), ("hi\n", 0));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "");
+        // until stdout is pointed at it
+        let (output, _) = capture(&format!("echo hi 3>{} >&3", file.display()), &mut shell).await;
+        assert_eq!(output, "");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hi\n");
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn noclobber_keeps_existing_files() {
+        let mut shell = Shell::new();
+        let file = temp_path("noclobber");
+        fs::write(&file, "old\n").unwrap();
+        capture("set -o noclobber", &mut shell).await;
+
+        let (_, exit_code) = capture(&format!("echo new > {}", file.display()), &mut shell).await;
+        assert_eq!(exit_code, 1);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "old\n");
+
+        let (_, exit_code) = capture(&format!("echo new >| {}", file.display()), &mut shell).await;
+        assert_eq!(exit_code, 0);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new\n");
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn and_redirects_send_both_streams_to_the_file() {
+        let mut shell = Shell::new();
+        let file = temp_path("and-redirect");
+        let both = "echo is a shell builtin\nnope-not-a-command: not found\n";
+        let (output, _) = capture(
+            &format!("type echo nope-not-a-command &> {}", file.display()),
+            &mut shell,
+        )
+        .await;
+        assert_eq!(output, "");
+        assert_eq!(fs::read_to_string(&file).unwrap(), both);
+        let (output, _) = capture(
+            &format!("type echo nope-not-a-command &>> {}", file.display()),
+            &mut shell,
+        )
+        .await;
+        assert_eq!(output, "");
+        assert_eq!(fs::read_to_string(&file).unwrap(), both.repeat(2));
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn type_fails_for_a_name_that_isnt_anything() {
+        let mut shell = Shell::new();
+        let (output, exit_code) = capture("type echo", &mut shell).await;
+        assert_eq!(
+            (output.as_str(), exit_code),
+            ("echo is a shell builtin\n", 0)
+        );
+        let (output, exit_code) = capture("type nope-not-a-command", &mut shell).await;
+        assert_eq!(
+            (output.as_str(), exit_code),
+            ("nope-not-a-command: not found\n", 1)
+        );
+        assert_eq!(
+            capture("type echo nope-not-a-command", &mut shell).await.1,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn here_string_is_the_input() {
+        let mut shell = Shell::new();
+        assert_eq!(
+            capture("cat <<< hello", &mut shell).await,
+            ("hello\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("x='a  b'; cat <<< \"$x\"", &mut shell).await,
+            ("a  b\n".to_string(), 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn and_or_short_circuit() {
+        let mut shell = Shell::new();
+        assert_eq!(
+            capture("true && echo yes", &mut shell).await,
+            ("yes\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("false && echo yes", &mut shell).await,
+            (String::new(), 1)
+        );
+        assert_eq!(
+            capture("false || echo no", &mut shell).await,
+            ("no\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("true || echo no", &mut shell).await,
+            (String::new(), 0)
+        );
+        assert_eq!(
+            capture("false && echo yes; echo $?", &mut shell).await,
+            ("1\n".to_string(), 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn builtin_writing_more_than_a_pipe_holds_finishes() {
+        let mut shell = Shell::new();
+        // a pipe holds 64 KiB, so the builtin can only finish writing while the program reads
+        assert_eq!(
+            capture("printf '%0100000d\\n' 0 | wc -c", &mut shell).await,
+            ("100001\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("printf '%0100000d\\n' 0 | cat | wc -c", &mut shell).await,
+            ("100001\n".to_string(), 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn printf_v_assigns_instead_of_writing() {
+        let mut shell = Shell::new();
+        assert_eq!(
+            capture("printf -v x '%05d' 42", &mut shell).await,
+            (String::new(), 0)
+        );
+        assert_eq!(shell.var("x").unwrap(), "00042");
+    }
+
+    #[tokio::test]
+    async fn subshell_changes_stay_inside_it() {
+        let mut shell = Shell::new();
+        let cwd = shell::working_dir();
+        capture("x=outer", &mut shell).await;
+        let (output, exit_code) = capture("(cd /; x=inner; pwd; echo $x)", &mut shell).await;
+        assert_eq!((output.as_str(), exit_code), ("/\ninner\n", 0));
+        assert_eq!(shell::working_dir(), cwd);
+        assert_eq!(shell.var("x").unwrap(), "outer");
+    }
+
+    #[tokio::test]
+    async fn errexit_stops_after_a_failure() {
+        let mut shell = Shell::new();
+        capture("set -e", &mut shell).await;
+        assert_eq!(
+            capture("echo a; false; echo b", &mut shell).await,
+            ("a\n".to_string(), 1)
+        );
+        // a failure on the left of `||` is handled, so it doesn't stop
+        assert_eq!(
+            capture("false || echo c; echo d", &mut shell).await,
+            ("c\nd\n".to_string(), 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn xtrace_writes_each_command_to_stderr() {
+        let mut shell = Shell::new();
+        capture("set -x", &mut shell).await;
+        // not to stdout
+        assert_eq!(
+            capture("{ x=1; echo $x; } 2>/dev/null", &mut shell).await,
+            ("1\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("{ x=2; echo $x; } 2>&1", &mut shell).await,
+            ("+ x=2\n+ echo 2\n2\n".to_string(), 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn integer_variables_are_assigned_arithmetic() {
+        let mut shell = Shell::new();
+        capture("y=4; declare -i n=2+3", &mut shell).await;
+        assert_eq!(shell.var("n").unwrap(), "5");
+        capture("n=n*y", &mut shell).await;
+        assert_eq!(shell.var("n").unwrap(), "20");
+        // plain again after +i
+        capture("declare +i n; n=1+1", &mut shell).await;
+        assert_eq!(shell.var("n").unwrap(), "1+1");
+    }
+
+    #[tokio::test]
+    async fn timeout_stops_a_program_that_runs_too_long() {
+        let mut shell = Shell::new();
+        let start = Instant::now();
+        assert_eq!(
+            capture("timeout 1 sleep 10", &mut shell).await,
+            (String::new(), 124)
+        );
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert_eq!(
+            capture("timeout 1 nope-not-a-command 2>&1", &mut shell).await,
+            ("timeout: nope-not-a-command: not found\n".to_string(), 127)
+        );
+    }
+
+    #[tokio::test]
+    async fn type_a_lists_every_match_in_path_order() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut shell = Shell::new();
+        let (first, second) = (temp_path("path-first"), temp_path("path-second"));
+        for dir in [&first, &second] {
+            fs::create_dir_all(dir).unwrap();
+            let tool = dir.join("type-a-tool");
+            fs::write(&tool, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        // ahead of the rest of PATH, so other tests still find their programs
+        let path = env::var_os("PATH").unwrap_or_default();
+        capture(
+            &format!("PATH={}:{}:$PATH", first.display(), second.display()),
+            &mut shell,
+        )
+        .await;
+        let expected = format!(
+            "type-a-tool is {}\ntype-a-tool is {}\n",
+            first.join("type-a-tool").display(),
+            second.join("type-a-tool").display()
+        );
+        let result = capture("type -a type-a-tool", &mut shell).await;
+        env::set_var("PATH", path);
+        assert_eq!(result, (expected, 0));
+        let _ = fs::remove_dir_all(&first);
+        let _ = fs::remove_dir_all(&second);
+    }
+
+    #[tokio::test]
+    async fn env_and_printenv_show_only_exported_variables() {
+        let mut shell = Shell::new();
+        capture(
+            "export ENV_TEST_PRESENT=yes; ENV_TEST_UNEXPORTED=no",
+            &mut shell,
+        )
+        .await;
+        let (output, exit_code) = capture("env", &mut shell).await;
+        assert!(output.lines().any(|line| line == "ENV_TEST_PRESENT=yes"));
+        assert!(!output.contains("ENV_TEST_UNEXPORTED"));
+        assert_eq!(exit_code, 0);
+        assert_eq!(
+            capture("printenv ENV_TEST_PRESENT", &mut shell).await,
+            ("yes\n".to_string(), 0)
+        );
+        assert_eq!(
+            capture("printenv ENV_TEST_UNEXPORTED", &mut shell).await,
+            (String::new(), 1)
+        );
+        // the ones there are still printed
+        assert_eq!(
+            capture("printenv ENV_TEST_MISSING ENV_TEST_PRESENT", &mut shell).await,
+            ("yes\n".to_string(), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn output_to_dev_null_is_dropped() {
+        let mut shell = Shell::new();
+        // anything not sent to /dev/null would come out through the 2>&1
+        let input = "{ echo out > /dev/null; cd nope-not-a-dir 2> /dev/null; \
+            cat <<< out > /dev/null; cat nope-not-a-file 2> /dev/null; } 2>&1";
+        assert_eq!(capture(input, &mut shell).await, (String::new(), 1));
+    }
+}
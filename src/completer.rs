@@ -0,0 +1,532 @@
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, env, ffi::OsString, path::Path};
+
+use rustyline::{
+    completion::Pair,
+    highlight::{CmdKind, Highlighter},
+    hint::Hinter,
+    history::{History, SearchDirection},
+    validate::{ValidationContext, ValidationResult, Validator},
+};
+
+use crate::{
+    builtins,
+    color::{self, Role},
+    parser::{self, is_executable},
+};
+
+#[derive(rustyline::Helper)]
+pub struct Completer {
+    builtins: HashSet<String>,
+    executables: RefCell<Executables>,
+    /** Whether to color the line being typed and suggest the rest of it, from `set -o highlight` */
+    pub highlight: bool,
+    /** The shell's alias names, so they count as commands when highlighting */
+    pub aliases: HashSet<String>,
+    /** The number and command of each background job, for completing job specs like `%1` */
+    pub jobs: Vec<(usize, String)>,
+    /** Set while a command line is being read at the prompt. Other lines, like an answer to `read`, are taken as they
+    are, without checking they're finished or hinting from history */
+    pub command_line: bool,
+}
+
+/** Executables found on PATH. They're scanned on the first completion and again whenever PATH changes */
+#[derive(Default)]
+struct Executables {
+    scanned: bool,
+    path: Option<OsString>,
+    names: HashSet<String>,
+}
+
+impl Completer {
+    pub fn new() -> Completer {
+        Completer {
+            builtins: builtins::names().iter().map(|b| b.to_string()).collect(),
+            executables: RefCell::new(Executables::default()),
+            highlight: true,
+            aliases: HashSet::new(),
+            jobs: Vec::new(),
+            command_line: false,
+        }
+    }
+
+    /** Rescans PATH if it changed since the last scan, or if it hasn't been scanned yet */
+    fn refresh_executables(&self) {
+        let path = env::var_os("PATH");
+        let mut executables = self.executables.borrow_mut();
+        if executables.scanned && executables.path == path {
+            return;
+        }
+
+        executables.names.clear();
+        for dir in env::split_paths(path.as_deref().unwrap_or_default()) {
+            // read_dir follows a symlinked directory, and skips entries that aren't directories at all
+            let read_dir = match dir.read_dir() {
+                Ok(read_dir) => read_dir,
+                Err(_) => continue,
+            };
+            for entry in read_dir.flatten() {
+                if is_executable(&entry.path()) {
+                    executables
+                        .names
+                        .insert(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        executables.scanned = true;
+        executables.path = path;
+    }
+}
+impl rustyline::completion::Completer for Completer {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> Result<(usize, Vec<Pair>), rustyline::error::ReadlineError> {
+        let mut start = line[..pos]
+            .rfind(|c: char| c.is_ascii_whitespace())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let mut word = &line[start..pos];
+
+        // the first word is a command, unless it's a path to one
+        let command = line[..start].split_whitespace().next();
+        if word.starts_with('%') && command.map(is_job_command).unwrap_or(false) {
+            return Ok((start, complete_job(word, &self.jobs)));
+        }
+        let mut options = if let Some(dollar) = variable_start(line, start, pos) {
+            start = dollar;
+            word = &line[start..pos];
+            complete_variable(word)
+        } else if command.is_none() && !word.contains('/') {
+            self.complete_command(word)
+        } else {
+            complete_path(word, command.map(is_directory_command).unwrap_or(false))
+        };
+
+        options.sort_unstable();
+
+        // fill in what all the options share first, the full list only shows once there's nothing left to fill in
+        if options.len() > 1 {
+            let prefix = longest_common_prefix(&options);
+            if prefix.len() > word.len() {
+                return Ok((start, vec![plain_pair(prefix)]));
+            }
+        }
+
+        return Ok((start, options.into_iter().map(plain_pair).collect()));
+    }
+}
+
+impl Highlighter for Completer {
+    /** Colors the main prompt, but not the ones rustyline shows itself like for searching history */
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        if !default {
+            return Cow::Borrowed(prompt);
+        }
+        return Cow::Owned(color::prompt(prompt));
+    }
+
+    /** Colors each command by whether it can be run, and dims comments */
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if !self.highlight {
+            return Cow::Borrowed(line);
+        }
+
+        let mut highlighted = String::new();
+        // whether the next word is a command, like at the start or after a ; or |
+        let mut command_position = true;
+        let mut rest = line;
+        loop {
+            let word_start = rest
+                .find(|c: char| !c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+            highlighted += &rest[..word_start];
+            rest = &rest[word_start..];
+            if rest.is_empty() {
+                break;
+            }
+            if rest.starts_with('#') {
+                highlighted += &color::highlight(rest, Role::Comment);
+                break;
+            }
+
+            let word = &rest[..word_end(rest)];
+            rest = &rest[word.len()..];
+            if word.starts_with(is_operator) {
+                command_position = true;
+                highlighted += word;
+                continue;
+            }
+            if !command_position || parser::split_assignment(word).is_some() {
+                highlighted += word;
+                continue;
+            }
+            // a keyword is followed by another command, except the name after for and the word after case
+            if is_keyword(word) {
+                command_position = !matches!(word, "for" | "case");
+                highlighted += word;
+                continue;
+            }
+            command_position = false;
+            // a word that's expanded can't be looked up until it runs
+            if word.contains(['\'', '"', '\\', '$', '`', '~']) {
+                highlighted += word;
+                continue;
+            }
+            let role = match self.is_command(word) {
+                true => Role::Command,
+                false => Role::UnknownCommand,
+            };
+            highlighted += &color::highlight(word, role);
+        }
+        return Cow::Owned(highlighted);
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        return Cow::Owned(color::highlight(hint, Role::Hint));
+    }
+
+    /** The line is highlighted again on every change, since a word's color can change as it's typed */
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        return self.highlight && !matches!(kind, CmdKind::MoveCursor);
+    }
+}
+
+impl Hinter for Completer {
+    type Hint = String;
+
+    /** Suggests the rest of the line from history, which Right accepts */
+    fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<String> {
+        // only while typing at the end, a hint in the middle of the line would cover what's after the cursor
+        if !self.command_line || !self.highlight || pos < line.len() {
+            return None;
+        }
+        return history_hint(line, ctx.history());
+    }
+}
+
+impl Validator for Completer {
+    /** Keeps editing on a new line while the input is unfinished, like inside quotes or an `if` without its `fi`, so
+    the whole command can be edited before it runs */
+    fn validate(
+        &self,
+        ctx: &mut ValidationContext,
+    ) -> Result<ValidationResult, rustyline::error::ReadlineError> {
+        if self.needs_more_lines(ctx.input()) {
+            return Ok(ValidationResult::Incomplete);
+        }
+        return Ok(ValidationResult::Valid(None));
+    }
+}
+
+impl Completer {
+    /** Whether the input needs more lines before it can run, only for a command line */
+    fn needs_more_lines(&self, input: &str) -> bool {
+        return self.command_line && parser::needs_more_lines(input);
+    }
+
+    /** Whether the word runs something: an alias, builtin, executable on PATH, or path to an executable */
+    fn is_command(&self, word: &str) -> bool {
+        if word.contains('/') {
+            return is_executable(Path::new(word));
+        }
+        if self.aliases.contains(word) || self.builtins.contains(word) {
+            return true;
+        }
+        self.refresh_executables();
+        return self.executables.borrow().names.contains(word);
+    }
+
+    fn complete_command(&self, word: &str) -> Vec<String> {
+        self.refresh_executables();
+        let executables = self.executables.borrow();
+
+        let mut options = Vec::new();
+        for complete_option in self.builtins.union(&executables.names) {
+            if complete_option.starts_with(word) {
+                options.push(complete_option.clone());
+            }
+        }
+
+        // we want a space when it completes in place for some reason
+        if options.len() == 1 {
+            options[0] += " ";
+        }
+        return options;
+    }
+}
+
+/** The rest of the most recent history entry that starts with the line and goes further. Entries over several lines
+are skipped, a hint has to fit on the line */
+fn history_hint(line: &str, history: &dyn History) -> Option<String> {
+    if line.trim().is_empty() || history.is_empty() {
+        return None;
+    }
+    let mut start = history.len() - 1;
+    loop {
+        let found = history
+            .starts_with(line, start, SearchDirection::Reverse)
+            .ok()??;
+        let rest = &found.entry[line.len()..];
+        if !rest.is_empty() && !rest.contains('\n') {
+            return Some(rest.to_string());
+        }
+        start = found.idx.checked_sub(1)?;
+    }
+}
+
+/** Where the word at the start of the text ends. A run of operators like `&&` is a word of its own, otherwise it goes
+until whitespace or an operator that isn't quoted */
+fn word_end(text: &str) -> usize {
+    if text.starts_with(is_operator) {
+        return text.find(|c| !is_operator(c)).unwrap_or(text.len());
+    }
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, char) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (char, quote) {
+            ('\\', None | Some('"')) => escaped = true,
+            ('\'' | '"', None) => quote = Some(char),
+            (_, Some(open)) if char == open => quote = None,
+            (_, None) if char.is_ascii_whitespace() || is_operator(char) => return i,
+            _ => (),
+        }
+    }
+    return text.len();
+}
+
+fn is_operator(c: char) -> bool {
+    return matches!(c, ';' | '|' | '&' | '(' | ')');
+}
+
+/** Words that start or continue a compound command, rather than naming a command to run */
+fn is_keyword(word: &str) -> bool {
+    return matches!(
+        word,
+        "if" | "then"
+            | "elif"
+            | "else"
+            | "fi"
+            | "while"
+            | "until"
+            | "for"
+            | "in"
+            | "do"
+            | "done"
+            | "case"
+            | "esac"
+            | "{"
+            | "}"
+            | "!"
+            | "time"
+    );
+}
+
+/** Where the `$NAME` being typed at the end of the word starts, if there is one outside of single quotes */
+fn variable_start(line: &str, start: usize, pos: usize) -> Option<usize> {
+    let dollar = start + line[start..pos].rfind('$')?;
+    let name = &line[dollar + 1..pos];
+    if !name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    let mut quote = None;
+    let mut escaped = false;
+    for char in line[..dollar].chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (char, quote) {
+            ('\\', None | Some('"')) => escaped = true,
+            ('\'' | '"', None) => quote = Some(char),
+            (_, Some(open)) if char == open => quote = None,
+            _ => (),
+        }
+    }
+    if quote == Some('\'') || escaped {
+        return None;
+    }
+    return Some(dollar);
+}
+
+/** Completes `$NAME` to environment variable names */
+fn complete_variable(word: &str) -> Vec<String> {
+    let mut options = Vec::new();
+    for (name, _) in env::vars_os() {
+        let name = name.to_string_lossy();
+        if name.starts_with(&word[1..]) {
+            options.push(format!("${}", name));
+        }
+    }
+    return options;
+}
+
+fn longest_common_prefix(options: &[String]) -> String {
+    let mut prefix = options[0].clone();
+    for option in &options[1..] {
+        while !option.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    return prefix;
+}
+
+/** An option that's shown the same as what it fills in */
+fn plain_pair(option: String) -> Pair {
+    return Pair {
+        display: option.clone(),
+        replacement: option,
+    };
+}
+
+/** Commands that take job specs like `%1` */
+fn is_job_command(command: &str) -> bool {
+    return matches!(command, "fg" | "bg" | "kill");
+}
+
+/** Completes `%N` to the numbers of the jobs, listed with their commands so it's clear which is which */
+fn complete_job(word: &str, jobs: &[(usize, String)]) -> Vec<Pair> {
+    let mut options: Vec<Pair> = jobs
+        .iter()
+        .filter(|(number, _)| format!("%{}", number).starts_with(word))
+        .map(|(number, command)| Pair {
+            display: format!("%{}  {}", number, command),
+            replacement: format!("%{}", number),
+        })
+        .collect();
+    if options.len() == 1 {
+        options[0].replacement += " ";
+    }
+    return options;
+}
+
+/** Commands that only take directories, so there's no point completing files for them */
+fn is_directory_command(command: &str) -> bool {
+    return matches!(command, "cd" | "pushd");
+}
+
+/** Completes files and directories, relative to the cwd or the directory typed so far. Directories get a trailing / and files a space */
+fn complete_path(word: &str, directories_only: bool) -> Vec<String> {
+    let (dir, file_prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let read_dir = match Path::new(if dir.is_empty() { "." } else { dir }).read_dir() {
+        Ok(read_dir) => read_dir,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut options = Vec::new();
+    for entry in read_dir.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // hidden files only show up when asked for
+        if !name.starts_with(file_prefix)
+            || (name.starts_with('.') && !file_prefix.starts_with('.'))
+        {
+            continue;
+        }
+        let is_dir = entry.path().is_dir();
+        if directories_only && !is_dir {
+            continue;
+        }
+        let suffix = if is_dir { "/" } else { " " };
+        options.push(format!("{}{}{}", dir, name, suffix));
+    }
+    return options;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::completion::Completer as _;
+
+    #[test]
+    fn completes_job_specs_with_their_commands() {
+        let jobs = vec![(1, "sleep 5".to_string()), (2, "make test".to_string())];
+
+        let options = complete_job("%", &jobs);
+        let replacements: Vec<&str> = options.iter().map(|o| o.replacement.as_str()).collect();
+        assert_eq!(replacements, ["%1", "%2"]);
+        assert_eq!(options[1].display, "%2  make test");
+
+        let options = complete_job("%2", &jobs);
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].replacement, "%2 ");
+
+        assert!(complete_job("%3", &jobs).is_empty());
+        assert!(complete_job("%", &[]).is_empty());
+    }
+
+    #[test]
+    fn hints_the_rest_of_the_latest_matching_entry() {
+        let mut history = rustyline::history::FileHistory::new();
+        for entry in ["git status", "echo one", "git commit", "echo 'a\nb'"] {
+            history.add(entry).unwrap();
+        }
+        assert_eq!(history_hint("git", &history).unwrap(), " commit");
+        assert_eq!(history_hint("git s", &history).unwrap(), "tatus");
+        // one over several lines wouldn't fit, so an older one is used
+        assert_eq!(history_hint("echo ", &history).unwrap(), "one");
+        // nothing left to add
+        assert_eq!(history_hint("echo one", &history), None);
+        assert_eq!(history_hint("ls", &history), None);
+        assert_eq!(history_hint("  ", &history), None);
+    }
+
+    #[test]
+    fn only_command_lines_are_checked_and_hinted() {
+        let mut history = rustyline::history::FileHistory::new();
+        history.add("don't stop").unwrap();
+        let ctx = rustyline::Context::new(&history);
+        let mut completer = Completer::new();
+
+        assert!(!completer.needs_more_lines("don't"));
+        assert_eq!(completer.hint("don", 3, &ctx), None);
+
+        completer.command_line = true;
+        assert!(completer.needs_more_lines("don't"));
+        assert_eq!(completer.hint("don", 3, &ctx).unwrap(), "'t stop");
+    }
+
+    #[test]
+    fn cd_completes_only_directories() {
+        let dir = env::temp_dir().join(format!("completion-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("scripts")).unwrap();
+        std::fs::write(dir.join("setup.sh"), "").unwrap();
+        let history = rustyline::history::FileHistory::new();
+        let ctx = rustyline::Context::new(&history);
+        let completer = Completer::new();
+        let replacements = |line: String| {
+            let (_, options) = completer.complete(&line, line.len(), &ctx).unwrap();
+            return options
+                .into_iter()
+                .map(|o| o.replacement)
+                .collect::<Vec<String>>();
+        };
+
+        let dir = dir.display();
+        assert_eq!(
+            replacements(format!("cd {}/s", dir)),
+            [format!("{}/scripts/", dir), format!("{}/src/", dir)]
+        );
+        assert_eq!(
+            replacements(format!("cat {}/se", dir)),
+            [format!("{}/setup.sh ", dir)]
+        );
+        assert!(replacements(format!("cd {}/se", dir)).is_empty());
+        let _ = std::fs::remove_dir_all(dir.to_string());
+    }
+}
@@ -0,0 +1,67 @@
+use std::{ffi::CString, fs, os::unix::ffi::OsStrExt, path::Path};
+
+/** Evaluates the args of `test` (without the closing `]`). Returns if the expression is true, or an error for a malformed expression */
+pub fn evaluate(args: &[String]) -> Result<bool, String> {
+    return match args {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        [bang, rest @ ..] if bang == "!" => evaluate(rest).map(|result| !result),
+        [operator, operand] => unary(operator, operand),
+        [left, operator, right] => binary(left, operator, right),
+        _ => Err("too many arguments".to_string()),
+    };
+}
+
+fn unary(operator: &str, operand: &str) -> Result<bool, String> {
+    let path = Path::new(operand);
+    return Ok(match operator {
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        "-s" => fs::metadata(path)
+            .map(|metadata| metadata.len() > 0)
+            .unwrap_or(false),
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        _ => return Err(format!("{}: unary operator expected", operator)),
+    });
+}
+
+fn binary(left: &str, operator: &str, right: &str) -> Result<bool, String> {
+    match operator {
+        "=" | "==" => return Ok(left == right),
+        "!=" => return Ok(left != right),
+        _ => (),
+    }
+
+    let compare: fn(&i64, &i64) -> bool = match operator {
+        "-eq" => i64::eq,
+        "-ne" => i64::ne,
+        "-lt" => i64::lt,
+        "-gt" => i64::gt,
+        "-le" => i64::le,
+        "-ge" => i64::ge,
+        _ => return Err(format!("{}: binary operator expected", operator)),
+    };
+    return Ok(compare(&integer(left)?, &integer(right)?));
+}
+
+/** Surrounding whitespace is allowed, like bash */
+fn integer(arg: &str) -> Result<i64, String> {
+    return arg
+        .trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", arg));
+}
+
+/** Asks the OS whether the file can be read, written, or run by this process, since that depends on more than the mode bits */
+fn access(path: &str, mode: libc::c_int) -> bool {
+    let path = match CString::new(Path::new(path).as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    return unsafe { libc::access(path.as_ptr(), mode) } == 0;
+}
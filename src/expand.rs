@@ -0,0 +1,451 @@
+use std::{env, iter::Peekable, process, str::Chars};
+
+use crate::{
+    glob,
+    parser::{self, QuoteState},
+    shell::{self, Shell},
+};
+
+pub async fn expand_words(words: &[String], shell: &mut Shell) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for word in words {
+        expanded.extend(expand_word(word, shell).await);
+    }
+    return expanded;
+}
+
+/** Expands ~, parameters, command substitutions, and filenames, and removes quotes. A word can become several (like "$@"
+or `*.txt`), or none when it expands to nothing */
+pub async fn expand_word(word: &str, shell: &mut Shell) -> Vec<String> {
+    let mut words = Vec::new();
+    for field in expand(word, shell, true, false).await {
+        // a pattern that matches nothing is left as it is
+        match glob::has_pattern(&field.pattern) {
+            true => match glob::expand(&field.pattern) {
+                paths if paths.is_empty() => words.push(field.text),
+                paths => words.extend(paths),
+            },
+            false => words.push(field.text),
+        }
+    }
+    return words;
+}
+
+/** Expands the value of an assignment like `x=$(echo a b)`, which stays one word */
+pub async fn expand_value(value: &str, shell: &mut Shell) -> String {
+    let fields = expand(value, shell, false, true).await;
+    return fields
+        .into_iter()
+        .map(|field| field.text)
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+/** Expands a case pattern, which stays one word. Anything quoted only matches itself */
+pub async fn expand_pattern(pattern: &str, shell: &mut Shell) -> String {
+    let fields = expand(pattern, shell, false, false).await;
+    return fields
+        .into_iter()
+        .map(|field| field.pattern)
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+/** A word an expansion produced */
+#[derive(Default)]
+struct Field {
+    text: String,
+    /** The text with anything that was quoted escaped, so only unquoted `*`, `?`, and `[` match filenames */
+    pattern: String,
+}
+
+/** The fields an expansion has produced so far, and the one it's building */
+#[derive(Default)]
+struct Fields {
+    done: Vec<Field>,
+    current: Field,
+}
+
+impl Fields {
+    fn push(&mut self, text: &str, quoted: bool) {
+        self.current.text.push_str(text);
+        if quoted {
+            self.current.pattern.push_str(&glob::escape(text));
+        } else {
+            self.current.pattern.push_str(text);
+        }
+    }
+
+    /** Ends the field being built and starts another */
+    fn split(&mut self) {
+        self.done.push(std::mem::take(&mut self.current));
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.current.text.is_empty();
+    }
+
+    /** Every field, including the one being built if keep_empty is set or it has anything in it */
+    fn finish(mut self, keep_empty: bool) -> Vec<Field> {
+        if !self.is_empty() || keep_empty {
+            self.split();
+        }
+        return self.done;
+    }
+}
+
+/** Expands a word, splitting unquoted substitutions on IFS if split is set. assignment is set for the value of an
+assignment, which has already had the name and = taken off */
+async fn expand(word: &str, shell: &mut Shell, split: bool, assignment: bool) -> Vec<Field> {
+    let mut fields = Fields::default();
+    let mut quote_state = QuoteState::None;
+    // quotes make a word even if there's nothing in them, like `echo ""`
+    let mut quoted = false;
+    let mut chars = word.chars().peekable();
+    // ~ is only a home directory at the start of the word, or in an assignment's value after a :, like
+    // `PATH=~/bin:~/sbin`. A word that looks like an assignment counts too, so `export DIR=~/src` works
+    let mut tilde_allowed = true;
+    let mut before_equals = !assignment && parser::split_assignment(word).is_some();
+    let mut in_value = assignment;
+
+    while let Some(char) = chars.next() {
+        let tilde = std::mem::replace(&mut tilde_allowed, false);
+        match quote_state {
+            QuoteState::None => match char {
+                '\'' => {
+                    quote_state = QuoteState::Single;
+                    quoted = true;
+                }
+                '"' => {
+                    quote_state = QuoteState::Double;
+                    quoted = true;
+                }
+                // `~user` isn't supported, so it's left as it is. In an assignment's value a : ends it too, so
+                // `PATH=~:~/bin` is two home directories, but `echo ~:` isn't an assignment and stays as it is
+                '~' if tilde
+                    && (matches!(chars.peek(), None | Some('/'))
+                        || in_value && chars.peek() == Some(&':')) =>
+                {
+                    match env::var_os("HOME").or_else(shell::passwd_home) {
+                        Some(home) => fields.push(&home.to_string_lossy(), true),
+                        None => fields.push("~", true),
+                    }
+                }
+                '=' if before_equals => {
+                    before_equals = false;
+                    in_value = true;
+                    tilde_allowed = true;
+                    fields.push("=", false);
+                }
+                ':' if in_value => {
+                    tilde_allowed = true;
+                    fields.push(":", false);
+                }
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        fields.push(&escaped.to_string(), true);
+                    }
+                }
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    let substitution = substitute(&mut chars, shell).await;
+                    if split {
+                        push_fields(&substitution, shell, &mut fields);
+                    } else {
+                        fields.push(&substitution, false);
+                    }
+                }
+                '$' => expand_parameter(&mut chars, shell, false, split, &mut fields),
+                _ => fields.push(&char.to_string(), false),
+            },
+            QuoteState::Single => {
+                if char == '\'' {
+                    quote_state = QuoteState::None;
+                } else {
+                    fields.push(&char.to_string(), true);
+                }
+            }
+            QuoteState::Double => match char {
+                '"' => quote_state = QuoteState::None,
+                '\\' => match chars.peek() {
+                    // fallthrough to adding the char
+                    Some('"' | '\\' | '$') => fields.push(&chars.next().unwrap().to_string(), true),
+                    // need to add the \ because it didn't escape anything
+                    _ => fields.push("\\", true),
+                },
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    let substitution = substitute(&mut chars, shell).await;
+                    fields.push(&substitution, true);
+                }
+                '$' => {
+                    // except "$@" with no parameters, which is no word at all
+                    if chars.peek() == Some(&'@') && shell.positional_parameters.is_empty() {
+                        quoted = false;
+                    }
+                    expand_parameter(&mut chars, shell, true, false, &mut fields)
+                }
+                _ => fields.push(&char.to_string(), true),
+            },
+        }
+    }
+    return fields.finish(quoted);
+}
+
+/** Expands parameters in a heredoc body. Quotes are kept, and a backslash only escapes `$`, `\`, and a newline */
+pub async fn expand_heredoc(body: &str, shell: &mut Shell) -> String {
+    let mut fields = Fields::default();
+    let mut chars = body.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => match chars.peek() {
+                Some('$' | '\\') => fields.push(&chars.next().unwrap().to_string(), true),
+                // an escaped newline joins the lines
+                Some('\n') => {
+                    chars.next();
+                }
+                _ => fields.push("\\", true),
+            },
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                let substitution = substitute(&mut chars, shell).await;
+                fields.push(&substitution, true);
+            }
+            '$' => expand_parameter(&mut chars, shell, true, false, &mut fields),
+            _ => fields.push(&char.to_string(), true),
+        }
+    }
+    let fields = fields.finish(true);
+    return fields
+        .into_iter()
+        .map(|field| field.text)
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+/** Runs the command of a `$(...)` after the `$(`, giving what it printed without the trailing newlines. The output is
+text, not more of the word, so quotes and `$` in it are kept as they are: `x=$(echo '$HOME')` sets x to `$HOME`. It's
+only split and globbed, and only when it isn't quoted */
+async fn substitute(chars: &mut Peekable<Chars<'_>>, shell: &mut Shell) -> String {
+    // the tokenizer already made sure the ) is there
+    let command = parser::read_substitution(chars).unwrap_or_default();
+    let command = match parser::parse_input(&command, shell) {
+        Some(command) => command,
+        None => return String::new(),
+    };
+    let (output, _) = Box::pin(command.capture(shell)).await;
+    let output = String::from_utf8_lossy(&output);
+    return output.trim_end_matches('\n').to_string();
+}
+
+/** Splits an unquoted substitution into words on IFS. The first and last join up with the text around them, like `a$(echo b c)d` is `ab cd` */
+fn push_fields(text: &str, shell: &Shell, fields: &mut Fields) {
+    let ifs = ifs(shell);
+    let starts_with_ifs = text.starts_with(|c: char| ifs.contains(c));
+    let ends_with_ifs = text.ends_with(|c: char| ifs.contains(c));
+    let mut split_text = split_fields(text, &ifs).into_iter();
+
+    // a separator at the start ends the word before it
+    if starts_with_ifs && !fields.is_empty() {
+        fields.split();
+    }
+    if let Some(first) = split_text.next() {
+        fields.push(&first, false);
+    }
+    for field in split_text {
+        fields.split();
+        fields.push(&field, false);
+    }
+    // and one at the end keeps whatever comes after out of the last word
+    if ends_with_ifs && !fields.is_empty() {
+        fields.split();
+    }
+}
+
+/** The field separators, from `IFS` with the default of space, tab, and newline */
+pub fn ifs(shell: &Shell) -> String {
+    return shell.var("IFS").unwrap_or(" \t\n".to_string());
+}
+
+fn is_ifs_whitespace(c: char, ifs: &str) -> bool {
+    return c.is_ascii_whitespace() && ifs.contains(c);
+}
+
+/** Splits text into fields on the IFS characters. Runs of IFS whitespace count as one separator, but every other IFS
+character separates a field of its own, so `a::b` with `IFS=:` is `a`, an empty field, and `b` */
+pub fn split_fields(text: &str, ifs: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut rest = text.trim_start_matches(|c| is_ifs_whitespace(c, ifs));
+    while !rest.is_empty() {
+        let (field, next) = next_field(rest, ifs);
+        fields.push(field.to_string());
+        rest = next;
+    }
+    return fields;
+}
+
+/** Splits the first field off the text, returning it and the text after its separator. A separator is IFS whitespace
+around at most one other IFS character */
+pub fn next_field<'a>(text: &'a str, ifs: &str) -> (&'a str, &'a str) {
+    let is_whitespace = |c| is_ifs_whitespace(c, ifs);
+    let text = text.trim_start_matches(is_whitespace);
+    let end = text.find(|c: char| ifs.contains(c)).unwrap_or(text.len());
+    let mut rest = text[end..].trim_start_matches(is_whitespace);
+    if let Some(separator) = rest.chars().next().filter(|c| ifs.contains(*c)) {
+        rest = rest[separator.len_utf8()..].trim_start_matches(is_whitespace);
+    }
+    return (&text[..end], rest);
+}
+
+/** Expands the parameter after a `$`. $@ (and unquoted $*) give each positional parameter its own word, and with split
+set an unquoted one is split on IFS too, like `set -- "a b" c; echo $@` being three words */
+fn expand_parameter(
+    chars: &mut Peekable<Chars>,
+    shell: &Shell,
+    quoted: bool,
+    split: bool,
+    fields: &mut Fields,
+) {
+    let name = match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut name = String::new();
+            while let Some(char) = chars.next() {
+                if char == '}' {
+                    break;
+                }
+                name.push(char);
+            }
+            name
+        }
+        Some(char) if char.is_ascii_digit() || "#@*$?".contains(*char) => {
+            chars.next().unwrap().to_string()
+        }
+        Some(char) if *char == '_' || char.is_ascii_alphabetic() => {
+            let mut name = String::new();
+            while let Some(char) = chars.peek() {
+                if *char != '_' && !char.is_ascii_alphanumeric() {
+                    break;
+                }
+                name.push(*char);
+                chars.next();
+            }
+            name
+        }
+        // a lone $ is just a $
+        _ => {
+            fields.push("$", quoted);
+            return;
+        }
+    };
+
+    if name == "@" || (name == "*" && !quoted) {
+        let split = split && !quoted;
+        for (i, parameter) in shell.positional_parameters.iter().enumerate() {
+            // one split on IFS has already ended its last word if it ended with a separator
+            if i > 0 && !(split && fields.is_empty()) {
+                fields.split();
+            }
+            match split {
+                true => push_fields(parameter, shell, fields),
+                false => fields.push(parameter, quoted),
+            }
+        }
+        return;
+    }
+    fields.push(&parameter(&name, shell), quoted);
+}
+
+/** The value of a named, positional, or special parameter. Unset parameters are empty */
+fn parameter(name: &str, shell: &Shell) -> String {
+    if let Ok(index) = name.parse::<usize>() {
+        if index == 0 {
+            return shell.shell_name.clone();
+        }
+        return shell
+            .positional_parameters
+            .get(index - 1)
+            .cloned()
+            .unwrap_or_default();
+    }
+    return match name {
+        "#" => shell.positional_parameters.len().to_string(),
+        "?" => shell.last_status.to_string(),
+        "@" => shell.positional_parameters.join(" "),
+        // "$*" is one word, joined by the first character of IFS (or nothing if IFS is empty)
+        "*" => {
+            let separator = ifs(shell)
+                .chars()
+                .next()
+                .map(String::from)
+                .unwrap_or_default();
+            shell.positional_parameters.join(&separator)
+        }
+        "$" => process::id().to_string(),
+        _ => shell.var(name).unwrap_or_default(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+
+    #[tokio::test]
+    async fn substitution_output_is_not_expanded_again() {
+        let mut shell = Shell::new();
+        commands::run_text("x=$(echo '$HOME')", &mut shell).await;
+        assert_eq!(shell.var("x").unwrap(), "$HOME");
+        assert_eq!(expand_word("$(echo '$HOME')", &mut shell).await, ["$HOME"]);
+        assert_eq!(
+            expand_word("\"$(echo \"'a'\")\"", &mut shell).await,
+            ["'a'"]
+        );
+        // but unquoted it's still split into words
+        assert_eq!(expand_word("$(echo 'a  b')", &mut shell).await, ["a", "b"]);
+        assert_eq!(
+            expand_word("\"$(echo 'a  b')\"", &mut shell).await,
+            ["a  b"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unquoted_parameters_are_split_but_quoted_ones_are_not() {
+        let mut shell = Shell::new();
+        shell.positional_parameters = vec!["a b".to_string(), "c".to_string()];
+        assert_eq!(expand_word("$@", &mut shell).await, ["a", "b", "c"]);
+        assert_eq!(expand_word("$*", &mut shell).await, ["a", "b", "c"]);
+        assert_eq!(expand_word("\"$@\"", &mut shell).await, ["a b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn positional_parameters() {
+        let mut shell = Shell::new();
+        shell.positional_parameters = vec!["one".to_string(), "two words".to_string()];
+        assert_eq!(expand_word("$#", &mut shell).await, ["2"]);
+        assert_eq!(expand_word("$1", &mut shell).await, ["one"]);
+        assert_eq!(expand_word("\"$2\"", &mut shell).await, ["two words"]);
+        assert_eq!(expand_word("${1}x", &mut shell).await, ["onex"]);
+        assert_eq!(expand_word("\"$3\"", &mut shell).await, [""]);
+        assert_eq!(
+            expand_word("x\"$@\"y", &mut shell).await,
+            ["xone", "two wordsy"]
+        );
+
+        shell.positional_parameters.clear();
+        assert_eq!(expand_word("$#", &mut shell).await, ["0"]);
+        // with nothing to expand to, "$@" isn't even an empty word
+        assert!(expand_word("\"$@\"", &mut shell).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tilde_after_a_colon_expands_only_in_assignments() {
+        let mut shell = Shell::new();
+        let home = env::var_os("HOME").or_else(shell::passwd_home).unwrap();
+        let home = home.to_string_lossy();
+        commands::run_text("x=a:~/b", &mut shell).await;
+        assert_eq!(shell.var("x").unwrap(), format!("a:{}/b", home));
+        assert_eq!(expand_word("a:~/b", &mut shell).await, ["a:~/b"]);
+    }
+}
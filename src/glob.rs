@@ -0,0 +1,174 @@
+use std::{fs, path::Path};
+
+/** If the pattern has an unescaped `*`, `?`, or `[`, so it could match more than its own text */
+pub fn has_pattern(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    return false;
+}
+
+/** Escapes the text so it only matches itself */
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for char in text.chars() {
+        if matches!(char, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    return escaped;
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(char),
+        }
+    }
+    return text;
+}
+
+/** If the text matches the pattern. `*` matches anything, `?` any one character, and `[...]` one of the characters in it
+(with ranges like `a-z`, and `!` or `^` first to match anything else). A backslash makes the next character literal */
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    return matches_from(&pattern, &text);
+}
+
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    return match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|i| matches_from(&pattern[1..], &text[i..])),
+        Some('?') => !text.is_empty() && matches_from(&pattern[1..], &text[1..]),
+        Some('[') if bracket_end(pattern).is_some() => {
+            let end = bracket_end(pattern).unwrap();
+            match text.first() {
+                Some(char) => {
+                    in_bracket(&pattern[1..end], *char)
+                        && matches_from(&pattern[end + 1..], &text[1..])
+                }
+                None => false,
+            }
+        }
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && matches_from(&pattern[2..], &text[1..])
+        }
+        Some(char) => text.first() == Some(char) && matches_from(&pattern[1..], &text[1..]),
+    };
+}
+
+/** Where the `]` closing the bracket expression at the start of the pattern is. A `]` right after the `[` (or `[!`) is
+part of the set. Without a closing `]`, the `[` is just a character */
+fn bracket_end(pattern: &[char]) -> Option<usize> {
+    let mut i = 1;
+    if matches!(pattern.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    while i < pattern.len() {
+        match pattern[i] {
+            '\\' => i += 1,
+            ']' => return Some(i),
+            _ => (),
+        }
+        i += 1;
+    }
+    return None;
+}
+
+/** If the character is in the set between the brackets */
+fn in_bracket(set: &[char], char: char) -> bool {
+    let (negated, set) = match set.first() {
+        Some('!' | '^') => (true, &set[1..]),
+        _ => (false, set),
+    };
+    // each character, and whether it was escaped, since an escaped - isn't a range
+    let mut members = Vec::new();
+    let mut i = 0;
+    while i < set.len() {
+        let escaped = set[i] == '\\' && i + 1 < set.len();
+        if escaped {
+            i += 1;
+        }
+        members.push((set[i], escaped));
+        i += 1;
+    }
+
+    let mut found = false;
+    let mut i = 0;
+    while i < members.len() {
+        // a - between two characters is a range, at either end it's just a -
+        if i + 2 < members.len() && members[i + 1] == ('-', false) {
+            found |= members[i].0 <= char && char <= members[i + 2].0;
+            i += 3;
+        } else {
+            found |= members[i].0 == char;
+            i += 1;
+        }
+    }
+    return found != negated;
+}
+
+/** The paths matching the pattern, sorted. Each part between slashes is matched against the names in the directory
+before it. Hidden files only match a part that starts with a `.`. Empty if nothing matches */
+pub fn expand(pattern: &str) -> Vec<String> {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let mut paths = vec![String::new()];
+    for (i, part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        let mut next = Vec::new();
+        for path in &paths {
+            // the empty part before a leading / (or between //, or after a trailing /) adds nothing but the slash
+            if part.is_empty() {
+                next.push(path.clone());
+            } else if !has_pattern(part) {
+                next.push(format!("{}{}", path, unescape(part)));
+            } else {
+                let dir = if path.is_empty() { "." } else { path.as_str() };
+                let entries = match fs::read_dir(dir) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+                let mut names = Vec::new();
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name.starts_with('.') && !part.starts_with('.') {
+                        continue;
+                    }
+                    if matches(part, &name) {
+                        names.push(format!("{}{}", path, name));
+                    }
+                }
+                next.extend(names);
+            }
+        }
+
+        paths = Vec::new();
+        for path in next {
+            if last {
+                // literal parts are kept only if they exist
+                if Path::new(&path).symlink_metadata().is_ok() {
+                    paths.push(path);
+                }
+            } else if path.is_empty() || Path::new(&path).is_dir() {
+                paths.push(path + "/");
+            }
+        }
+    }
+    paths.sort();
+    return paths;
+}
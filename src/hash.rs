@@ -0,0 +1,69 @@
+use std::{collections::HashMap, env, ffi::OsString, path::PathBuf};
+
+use crate::parser;
+
+/** Where commands were found on PATH, so each name is only searched for once. Everything is forgotten when PATH
+changes */
+#[derive(Default)]
+pub struct HashTable {
+    /** The PATH the entries were found on */
+    path: Option<OsString>,
+    /** Each name's path, and how many times it's been used */
+    entries: HashMap<String, (PathBuf, usize)>,
+}
+
+impl HashTable {
+    /** The path the name runs, from the table if it's there or the first PATH match otherwise, which is then added */
+    pub fn find(&mut self, name: &str) -> Option<PathBuf> {
+        self.check_path();
+        if let Some((path, hits)) = self.entries.get_mut(name) {
+            // a file that's gone (or stopped being executable) is searched for again
+            if parser::is_executable(path) {
+                *hits += 1;
+                return Some(path.clone());
+            }
+            self.entries.remove(name);
+        }
+
+        let path = parser::find_executables(name).into_iter().next()?;
+        self.entries.insert(name.to_string(), (path.clone(), 1));
+        return Some(path);
+    }
+
+    /** Adds the name to the table without using it, searching PATH unless the path is given. Returns false if it
+    couldn't be found */
+    pub fn add(&mut self, name: &str, path: Option<PathBuf>) -> bool {
+        self.check_path();
+        let path = match path.or_else(|| parser::find_executables(name).into_iter().next()) {
+            Some(path) => path,
+            None => return false,
+        };
+        self.entries.insert(name.to_string(), (path, 0));
+        return true;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /** Each name's path and hits, sorted by name */
+    pub fn entries(&mut self) -> Vec<(&String, &PathBuf, usize)> {
+        self.check_path();
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(name, (path, hits))| (name, path, *hits))
+            .collect();
+        entries.sort();
+        return entries;
+    }
+
+    /** Forgets everything if PATH changed since the entries were found */
+    fn check_path(&mut self) {
+        let path = env::var_os("PATH");
+        if self.path != path {
+            self.entries.clear();
+            self.path = path;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use rustyline::history::{History, SearchDirection};
+
+/** Replaces history references in a line typed at the prompt: `!!` is the last command, `!N` is entry N as `history`
+numbers them, `!-N` is the Nth command back, and `!prefix` is the most recent command starting with the prefix. None if
+the line has no references. The error is the message to show when one can't be found */
+pub fn expand(line: &str, history: &dyn History) -> Result<Option<String>, String> {
+    let mut expanded = String::new();
+    let mut found = false;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut chars = line.char_indices();
+
+    while let Some((i, char)) = chars.next() {
+        if escaped {
+            escaped = false;
+            expanded.push(char);
+            continue;
+        }
+        match (char, quote) {
+            ('\\', None | Some('"')) => escaped = true,
+            ('\'' | '"', None) => quote = Some(char),
+            (_, Some(open)) if char == open => quote = None,
+            // `$!` is the last background job, and a ! before a space or = is just a !, like `[ ! -f x ]` or `a != b`
+            ('!', None) if !expanded.ends_with('$') => {
+                let event = event(&line[i + 1..]);
+                if !event.is_empty() {
+                    expanded += &find(event, history)?;
+                    found = true;
+                    for _ in 0..event.chars().count() {
+                        chars.next();
+                    }
+                    continue;
+                }
+            }
+            _ => (),
+        }
+        expanded.push(char);
+    }
+    return Ok(found.then_some(expanded));
+}
+
+/** The reference after a `!`, like `!` or `12` or `git`. It's empty if the `!` isn't a reference at all */
+fn event(text: &str) -> &str {
+    if text.starts_with('!') {
+        return &text[..1];
+    }
+    if let Some(number) = text.strip_prefix('-') {
+        let digits = number
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(number.len());
+        return &text[..digits + 1];
+    }
+    let end = text
+        .find(|c: char| c.is_ascii_whitespace() || "=();|&<>'\"".contains(c))
+        .unwrap_or(text.len());
+    return &text[..end];
+}
+
+/** The history entry a reference is for */
+fn find(event: &str, history: &dyn History) -> Result<String, String> {
+    let index = match event {
+        "!" => history.len().checked_sub(1),
+        _ if event.starts_with('-') => event[1..]
+            .parse::<usize>()
+            .ok()
+            .and_then(|back| history.len().checked_sub(back)),
+        _ if event.starts_with(|c: char| c.is_ascii_digit()) => event
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1)),
+        _ => history
+            .len()
+            .checked_sub(1)
+            .and_then(|last| {
+                history
+                    .starts_with(event, last, SearchDirection::Reverse)
+                    .ok()?
+            })
+            .map(|result| result.idx),
+    };
+    let entry = index.and_then(|index| history.get(index, SearchDirection::Forward).ok()?);
+    return match entry {
+        Some(entry) => Ok(entry.entry.into_owned()),
+        None => Err(format!("!{}: event not found", event)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use rustyline::history::FileHistory;
+
+    use super::*;
+
+    fn history(entries: &[&str]) -> FileHistory {
+        let mut history = FileHistory::new();
+        for entry in entries {
+            history.add(entry).unwrap();
+        }
+        return history;
+    }
+
+    #[test]
+    fn expands_references() {
+        let history = history(&["ls -l", "git status", "echo hi"]);
+        assert_eq!(
+            expand("sudo !!", &history).unwrap().unwrap(),
+            "sudo echo hi"
+        );
+        assert_eq!(expand("!1", &history).unwrap().unwrap(), "ls -l");
+        assert_eq!(
+            expand("!-2 --short", &history).unwrap().unwrap(),
+            "git status --short"
+        );
+        assert_eq!(expand("!gi", &history).unwrap().unwrap(), "git status");
+        assert_eq!(
+            expand("!l; !e", &history).unwrap().unwrap(),
+            "ls -l; echo hi"
+        );
+    }
+
+    #[test]
+    fn leaves_lines_without_references() {
+        let history = history(&["echo hi"]);
+        assert_eq!(expand("echo hi", &history).unwrap(), None);
+        assert_eq!(expand("[ ! -f x ]", &history).unwrap(), None);
+        assert_eq!(expand("[ a != b ]", &history).unwrap(), None);
+        assert_eq!(expand("echo $!", &history).unwrap(), None);
+        assert_eq!(expand("echo '!!' \\!!", &history).unwrap(), None);
+    }
+
+    #[test]
+    fn missing_events_are_errors() {
+        let history = history(&["echo hi"]);
+        assert_eq!(expand("!5", &history).unwrap_err(), "!5: event not found");
+        assert_eq!(expand("!-3", &history).unwrap_err(), "!-3: event not found");
+        assert_eq!(expand("!0", &history).unwrap_err(), "!0: event not found");
+        assert_eq!(
+            expand("!git", &history).unwrap_err(),
+            "!git: event not found"
+        );
+        assert_eq!(
+            expand("!!", &FileHistory::new()).unwrap_err(),
+            "!!: event not found"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::process::ExitStatus;
+
+use tokio::process::Child;
+
+/** A command started in the background with `&` */
+pub struct Job {
+    pub number: usize,
+    pub pid: u32,
+    /** The command as it was typed, for listing */
+    pub command: String,
+    pub child: Child,
+    /** Set once the job has finished, so it can be reported */
+    pub status: Option<ExitStatus>,
+}
+
+impl Job {
+    /** Running, Done, or Exit with the code, as `jobs` shows it */
+    pub fn state(&self) -> String {
+        return match self.status.map(|status| status.code()) {
+            None => "Running".to_string(),
+            Some(Some(0)) => "Done".to_string(),
+            Some(Some(code)) => format!("Exit {}", code),
+            Some(None) => "Terminated".to_string(),
+        };
+    }
+}
+
+/** Background jobs, in the order they were started */
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    /** Adds a job, numbered one past the highest job still in the table. Returns the job number */
+    pub fn add(&mut self, child: Child, command: String) -> usize {
+        let number = self.jobs.last().map(|job| job.number + 1).unwrap_or(1);
+        self.jobs.push(Job {
+            number,
+            pid: child.id().unwrap_or_default(),
+            command,
+            child,
+            status: None,
+        });
+        return number;
+    }
+
+    /** Checks every job without blocking, recording the ones that have finished */
+    pub fn poll(&mut self) {
+        for job in &mut self.jobs {
+            if job.status.is_none() {
+                job.status = job.child.try_wait().ok().flatten();
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        return self.jobs.iter();
+    }
+
+    /** Drops jobs that have finished, once they've been reported */
+    pub fn remove_finished(&mut self) {
+        self.jobs.retain(|job| job.status.is_none());
+    }
+
+    /** Takes the job out of the table, like when it's brought to the foreground */
+    pub fn remove(&mut self, number: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.number == number)?;
+        return Some(self.jobs.remove(index));
+    }
+
+    /** Finds the job number for a job spec like `%1`, `1`, `%+`, `%%`, or `%-`. No spec means the current job */
+    pub fn find(&self, spec: Option<&str>) -> Option<usize> {
+        let spec = spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec));
+        return match spec {
+            None | Some("" | "+" | "%") => self.current(),
+            Some("-") => self.previous(),
+            Some(number) => {
+                let number = number.parse().ok()?;
+                self.jobs
+                    .iter()
+                    .any(|job| job.number == number)
+                    .then_some(number)
+            }
+        };
+    }
+
+    /** The most recently started job, marked with `+` */
+    pub fn current(&self) -> Option<usize> {
+        return self.jobs.last().map(|job| job.number);
+    }
+
+    /** The job started before the current one, marked with `-` */
+    pub fn previous(&self) -> Option<usize> {
+        return self.jobs.iter().rev().nth(1).map(|job| job.number);
+    }
+
+    /** The job's line in `jobs`, like `[1]+  Running                 sleep 5 &`, with the PID too if long */
+    pub fn describe(&self, job: &Job, long: bool) -> String {
+        let pid = if long {
+            format!("{} ", job.pid)
+        } else {
+            String::new()
+        };
+        let ampersand = if job.status.is_none() { " &" } else { "" };
+        return format!(
+            "[{}]{}  {}{:<24}{}{}",
+            job.number,
+            self.marker(job.number),
+            pid,
+            job.state(),
+            job.command,
+            ampersand
+        );
+    }
+
+    /** What `jobs` shows: a line for each job, with its PID too if long, or with pids only the PIDs */
+    pub fn list(&self, long: bool, pids: bool) -> String {
+        let mut lines = String::new();
+        for job in &self.jobs {
+            match pids {
+                true => lines += &format!("{}\n", job.pid),
+                false => lines += &(self.describe(job, long) + "\n"),
+            }
+        }
+        return lines;
+    }
+
+    /** Describes the jobs that finished since the last check, and drops them */
+    pub fn take_finished(&mut self) -> Vec<String> {
+        self.poll();
+        let finished = self
+            .jobs
+            .iter()
+            .filter(|job| job.status.is_some())
+            .map(|job| self.describe(job, false))
+            .collect();
+        self.remove_finished();
+        return finished;
+    }
+
+    /** The `+`/`-` marker bash shows next to the current and previous jobs */
+    pub fn marker(&self, number: usize) -> char {
+        if self.current() == Some(number) {
+            return '+';
+        }
+        if self.previous() == Some(number) {
+            return '-';
+        }
+        return ' ';
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_pids_with_long_and_only_pids_with_pids() {
+        let mut jobs = Jobs::default();
+        for seconds in ["5", "6"] {
+            let child = tokio::process::Command::new("sleep")
+                .arg(seconds)
+                .spawn()
+                .unwrap();
+            jobs.add(child, format!("sleep {}", seconds));
+        }
+        let pids: Vec<u32> = jobs.iter().map(|job| job.pid).collect();
+
+        assert_eq!(
+            jobs.list(true, false),
+            format!(
+                "[1]-  {} Running                 sleep 5 &\n[2]+  {} Running                 sleep 6 &\n",
+                pids[0], pids[1]
+            )
+        );
+        assert_eq!(
+            jobs.list(false, true),
+            format!("{}\n{}\n", pids[0], pids[1])
+        );
+        assert!(!jobs.list(false, false).contains(&pids[0].to_string()));
+
+        for job in &mut jobs.jobs {
+            let _ = job.child.kill().await;
+        }
+    }
+}
@@ -1,82 +1,123 @@
-use std::{collections::HashSet, env};
+use std::{
+    env,
+    io::{self, ErrorKind, IsTerminal},
+};
 
-use rustyline::config::Configurer;
+use rustyline::error::ReadlineError;
+use shell::Shell;
 
+mod arithmetic;
+mod builtins;
+mod color;
 mod commands;
+mod completer;
+mod conditional;
+mod expand;
+mod glob;
+mod hash;
+mod history;
+mod jobs;
 mod parser;
+mod printf;
+mod prompt;
+mod shell;
+mod signal;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let mut editor = rustyline::Editor::new().unwrap();
-    editor.set_helper(Some(Completer::new()));
-    editor.set_completion_type(rustyline::CompletionType::List);
-
-    loop {
-        let input = editor.readline("$ ").unwrap();
-
-        let command = parser::parse_input(&input);
-        if command.is_some() {
-            command.unwrap().run().await;
-        }
+    let mut shell = Shell::new();
+    // `shell -c commands args...` runs the commands and `shell script.sh args...` the script, otherwise commands come
+    // from the prompt
+    let mut args = env::args().skip(1);
+    match args.next() {
+        Some(option) if option == "-c" => match args.next() {
+            Some(commands) => shell.run_command_string(commands, args.collect()).await,
+            None => {
+                eprintln!("{}: -c: option requires an argument", shell.shell_name);
+                shell.exit(2);
+            }
+        },
+        Some(script) => shell.run_script(script, args.collect()).await,
+        None => (),
     }
-}
+    signal::init_interactive();
+    shell.load_rc().await;
 
-#[derive(rustyline::Helper, rustyline::Highlighter, rustyline::Hinter, rustyline::Validator)]
-struct Completer {
-    complete_options: HashSet<String>,
-}
-impl Completer {
-    pub fn new() -> Completer {
-        let mut complete_options: HashSet<String> = HashSet::new();
-
-        // TODO Tie this more closely with the enum in commands.rs
-        let builtins = vec!["echo", "exit", "type", "pwd", "cd"];
-        builtins.iter().for_each(|b| {
-            complete_options.insert(b.to_string());
-        });
+    'prompt: loop {
+        // finished background jobs are reported just before the prompt, so they don't interrupt the previous command's output
+        for finished in shell.jobs.take_finished() {
+            eprintln!("{}", finished);
+        }
 
-        let paths = env::var_os("PATH").unwrap();
-        for path in env::split_paths(&paths) {
-            if path.is_file() {
-                complete_options.insert(path.file_name().unwrap().to_str().unwrap().to_string());
+        shell.sync_editor();
+        let mut input = match shell.read_command_line(&prompt::render(&shell)) {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => {
+                if let Some(exit_code) = commands::run_trap("SIGINT", &mut shell).await {
+                    shell.finish(exit_code).await;
+                }
+                continue;
+            }
+            // the terminal hanging up ends the input with a SIGHUP on the way
+            Err(ReadlineError::Eof) => {
+                let exit_code = commands::handle_signals(&mut shell).await;
+                shell.finish(exit_code.unwrap_or(0)).await
+            }
+            Err(ReadlineError::Io(error)) if error.kind() == ErrorKind::InvalidData => {
+                eprintln!("input is not valid UTF-8, ignoring the line");
                 continue;
             }
-            if path.is_dir() {
-                let dir = path.read_dir().unwrap();
-                for entry_option in dir {
-                    let entry = entry_option.unwrap();
-                    if entry.file_type().unwrap().is_file() {
-                        complete_options.insert(entry.file_name().into_string().unwrap());
-                    }
+            Err(error) => {
+                if let Some(exit_code) = commands::handle_signals(&mut shell).await {
+                    shell.finish(exit_code).await;
                 }
+                panic!("{}", error)
+            }
+        };
+        while parser::needs_more_lines(&input) {
+            match shell.read_command_line(&prompt::render_continuation(&shell)) {
+                Ok(line) => input += &format!("\n{}", line),
+                Err(ReadlineError::Interrupted) => continue 'prompt,
+                // parsing reports what was left unfinished
+                Err(ReadlineError::Eof) => break,
+                Err(error) => panic!("{}", error),
             }
         }
-        Completer { complete_options }
-    }
-}
-impl rustyline::completion::Completer for Completer {
-    type Candidate = String;
-
-    fn complete(
-        &self,
-        line: &str,
-        _pos: usize,
-        _ctx: &rustyline::Context<'_>,
-    ) -> Result<(usize, Vec<String>), rustyline::error::ReadlineError> {
-        let mut options = Vec::new();
-        for complete_option in &self.complete_options {
-            if complete_option.starts_with(line) {
-                options.push(complete_option.clone());
+        // references to earlier commands are replaced before the line goes in history, and the line they make is shown
+        // so it's clear what's about to run. Like bash, only when someone is typing, input piped in can have `[!a]`
+        let expansion = match io::stdin().is_terminal() {
+            true => history::expand(&input, shell.editor.history()),
+            false => Ok(None),
+        };
+        match expansion {
+            Ok(Some(expanded)) => {
+                println!("{}", expanded);
+                input = expanded;
+            }
+            Ok(None) => (),
+            Err(error) => {
+                eprintln!("{}", error);
+                continue;
             }
         }
-
-        options.sort_unstable();
-
-        // we want a space when it completes in place for some reason
-        if options.len() == 1 {
-            options[0] += " ";
+        if !input.trim().is_empty() {
+            shell.editor.add_history_entry(input.as_str()).unwrap();
         }
 
-        return Ok((0, options));
+        signal::clear_interrupt();
+        let command = parser::parse_input(&input, &shell);
+        if command.is_some() {
+            let exit_code = command.unwrap().run(&mut shell).await;
+            if exit_code.is_some() {
+                shell.finish(exit_code.unwrap()).await;
+            }
+        }
+        if let Some(exit_code) = commands::handle_signals(&mut shell).await {
+            shell.finish(exit_code).await;
+        }
+        // the terminal shows ^C where it was pressed, so the prompt starts on the next line
+        if signal::interrupted() && io::stderr().is_terminal() {
+            eprintln!();
+        }
     }
 }
@@ -1,201 +1,1392 @@
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
     env,
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
 };
 
-use crate::commands::{Command, IO};
+use crate::{
+    builtins, color,
+    commands::{Command, IO},
+    expand,
+    shell::Shell,
+};
+
+/** Parses a line of input. Any lines after the command line are the bodies of the line's heredocs */
+pub fn parse_input(input: &str, shell: &Shell) -> Option<Command> {
+    let input = strip_nul(input);
+    let (command_parts, rest) = match split_command_line(&input) {
+        Ok(split) => split,
+        Err(error) => {
+            eprint!("{}", color::error(&format!("syntax error: {}\n", error)));
+            return None;
+        }
+    };
+    let checked = check_groups(&command_parts)
+        .and_then(|_| check_operators(&command_parts))
+        .and_then(|_| check_redirects(&command_parts));
+    if let Err(error) = checked {
+        eprint!("{}", color::error(&format!("syntax error: {}\n", error)));
+        return None;
+    }
+    let mut lines = rest.into_iter().flat_map(|rest| rest.split('\n'));
+    let mut heredocs = read_heredocs(&command_parts, &mut lines);
+    let mut command_parts = expand_aliases(command_parts, shell);
+
+    return parse_list(&mut command_parts, &mut heredocs);
+}
+
+/** If the input isn't finished yet: the command line ends inside quotes, a `$(`, or a group like `if` or `while`, or with a `\`, or has heredocs that haven't been ended by their delimiter line */
+pub fn needs_more_lines(input: &str) -> bool {
+    let (command_parts, rest) = match split_command_line(input) {
+        Ok(split) => split,
+        Err(_) => return true,
+    };
+    let mut lines = rest.into_iter().flat_map(|rest| rest.split('\n'));
+    for (delimiter, strip_tabs) in heredoc_delimiters(&command_parts) {
+        if !lines
+            .by_ref()
+            .any(|line| heredoc_line(line, strip_tabs) == delimiter)
+        {
+            return true;
+        }
+    }
+    return false;
+}
+
+/** The heredoc operator (`<<` or `<<-`) at the word, as the raw delimiter, whether leading tabs are stripped, and whether the delimiter is the next word instead of attached */
+fn heredoc_operator<'a>(
+    command_part: &'a str,
+    next: Option<&'a String>,
+) -> Option<(&'a str, bool, bool)> {
+    let rest = command_part.strip_prefix("<<")?;
+    if rest.starts_with('<') {
+        return None;
+    }
+    let (rest, strip_tabs) = match rest.strip_prefix('-') {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    if rest.is_empty() {
+        return Some((next?.as_str(), strip_tabs, true));
+    }
+    return Some((rest, strip_tabs, false));
+}
+
+/** The delimiter of each heredoc in the command, with its quotes removed, and whether leading tabs are stripped */
+fn heredoc_delimiters(command_parts: &[String]) -> Vec<(String, bool)> {
+    let mut delimiters = Vec::new();
+    for (i, command_part) in command_parts.iter().enumerate() {
+        if let Some((delimiter, strip_tabs, _)) =
+            heredoc_operator(command_part, command_parts.get(i + 1))
+        {
+            delimiters.push((delimiter.replace(['\'', '"', '\\'], ""), strip_tabs));
+        }
+    }
+    return delimiters;
+}
+
+fn heredoc_line(line: &str, strip_tabs: bool) -> &str {
+    if strip_tabs {
+        return line.trim_start_matches('\t');
+    }
+    return line;
+}
+
+/** Reads the body of each heredoc in the command from the lines after it, in order */
+fn read_heredocs<'a>(
+    command_parts: &[String],
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> VecDeque<String> {
+    let mut heredocs = VecDeque::new();
+    for (delimiter, strip_tabs) in heredoc_delimiters(command_parts) {
+        let mut body = String::new();
+        let mut delimited = false;
+        for line in lines.by_ref() {
+            let line = heredoc_line(line, strip_tabs);
+            if line == delimiter {
+                delimited = true;
+                break;
+            }
+            body += line;
+            body.push('\n');
+        }
+        if !delimited {
+            eprintln!(
+                "warning: here-document delimited by end-of-file (wanted `{}')",
+                delimiter
+            );
+        }
+        heredocs.push_back(body);
+    }
+    return heredocs;
+}
+
+/** Replaces aliases at the start of each command with their values, which can have their own pipes and redirects */
+fn expand_aliases(command_parts: Vec<String>, shell: &Shell) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut expand_next = false;
+    for (i, command_part) in command_parts.iter().enumerate() {
+        if expand_next || is_command_start(&command_parts, i) {
+            expand_next = expand_alias(command_part, shell, &mut Vec::new(), &mut output);
+        } else {
+            expand_next = false;
+            output.push(command_part.clone());
+        }
+    }
+    return output;
+}
+
+/** An alias isn't expanded again inside its own expansion, so `alias ls='ls -F'` doesn't loop forever. Returns if the
+expansion ended with a blank, which means the word after it is checked for an alias too, like `alias sudo='sudo '` */
+fn expand_alias(
+    word: &str,
+    shell: &Shell,
+    expanding: &mut Vec<String>,
+    output: &mut Vec<String>,
+) -> bool {
+    let value = match shell.aliases.get(word) {
+        Some(value) if !expanding.iter().any(|name| name == word) => value,
+        _ => {
+            output.push(word.to_string());
+            return false;
+        }
+    };
+
+    // a value that ends inside quotes expands to nothing, rather than to mangled words
+    let mut value_parts = transform_input(value).unwrap_or_default().into_iter();
+    let ends_with_blank = value.ends_with([' ', '\t']);
+    let Some(first) = value_parts.next() else {
+        return ends_with_blank;
+    };
+    expanding.push(word.to_string());
+    let first_ends_with_blank = expand_alias(&first, shell, expanding, output);
+    expanding.pop();
+    if value_parts.len() == 0 {
+        // `alias a=b` passes on b's trailing blank
+        return ends_with_blank || first_ends_with_blank;
+    }
+    output.extend(value_parts);
+    return ends_with_blank;
+}
+
+/** NUL can't be passed to programs or stored in variables, so it's dropped with a warning like bash does */
+fn strip_nul(input: &str) -> Cow<'_, str> {
+    if !input.contains('\0') {
+        return Cow::Borrowed(input);
+    }
+    eprintln!("warning: ignored null byte in input");
+    return Cow::Owned(input.replace('\0', ""));
+}
+
+/** If the word at i starts a command, so it can be a keyword like `{` */
+fn is_command_start(command_parts: &[String], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+    return match command_parts[i - 1].as_str() {
+        ";" | ";;" | "&" | "|" | "&&" | "||" | "(" => true,
+        // the commands of a case clause start after its patterns
+        ")" => is_case_pattern_end(command_parts, i - 1),
+        // keywords are only keywords where a command starts, `echo then ls` is just words
+        "{" | "if" | "then" | "elif" | "else" | "while" | "until" | "do" | "time" => {
+            is_command_start(command_parts, i - 1)
+        }
+        _ => false,
+    };
+}
+
+/** Parentheses open a subshell anywhere (except before a case pattern), but `{`, `if`, `case`, and loops only open a
+group where a command starts, `echo {` just prints it */
+fn opens_group(command_parts: &[String], i: usize) -> bool {
+    return match command_parts[i].as_str() {
+        "(" => !is_case_clause_start(command_parts, i),
+        "{" | "if" | "while" | "until" | "for" | "case" => is_command_start(command_parts, i),
+        _ => false,
+    };
+}
 
-pub fn parse_input(input: &str) -> Option<Command> {
-    let mut command_parts = transform_input(input);
+/** Like `{`, `}`, `fi`, and `done` only close a group where a command starts, so `{ echo a; }` needs the `;`. The `)`
+after a case pattern doesn't close anything */
+fn closes_group(command_parts: &[String], i: usize) -> bool {
+    return match command_parts[i].as_str() {
+        ")" => !is_case_pattern_end(command_parts, i),
+        "}" | "fi" | "done" => is_command_start(command_parts, i),
+        "esac" => is_command_start(command_parts, i) || is_case_clause_start(command_parts, i),
+        _ => false,
+    };
+}
 
-    return parse_redirect(&mut command_parts);
+/** If a case clause could start at i, right after `case word in` or the `;;` ending the clause before */
+fn is_case_clause_start(command_parts: &[String], i: usize) -> bool {
+    // clauses can be on their own lines
+    let mut i = i;
+    while i > 0 && command_parts[i - 1] == ";" {
+        i -= 1;
+    }
+    if i == 0 {
+        return false;
+    }
+    return command_parts[i - 1] == ";;"
+        || command_parts[i - 1] == "in"
+            && i >= 3
+            && command_parts[i - 3] == "case"
+            && is_command_start(command_parts, i - 3);
 }
 
-fn parse_redirect(command_parts: &mut Vec<String>) -> Option<Command> {
-    let mut out_path = RedirectType::None;
-    let mut err_path = RedirectType::None;
+/** If the `)` at i ends the patterns of a case clause, like `a | b)`, with an optional `(` before them */
+fn is_case_pattern_end(command_parts: &[String], i: usize) -> bool {
+    let is_pattern = |cp: &str| !matches!(cp, ";" | ";;" | "&" | "|" | "&&" | "||" | "(" | ")");
+    if command_parts[i] != ")" || i == 0 || !is_pattern(&command_parts[i - 1]) {
+        return false;
+    }
+    let mut start = i - 1;
+    while start >= 2 && command_parts[start - 1] == "|" && is_pattern(&command_parts[start - 2]) {
+        start -= 2;
+    }
+    if start > 0 && command_parts[start - 1] == "(" {
+        start -= 1;
+    }
+    return is_case_clause_start(command_parts, start);
+}
+
+/** Every group is closed by the same kind it was opened with, each part of it has a command, and the keywords of an if
+or loop come in order. A missing close is caught by the tokenizer, since more lines could still be coming */
+fn check_groups(command_parts: &[String]) -> Result<(), String> {
+    // the innermost group is last, ifs are tracked by their latest keyword
+    let mut open: Vec<&str> = Vec::new();
+    for (i, command_part) in command_parts.iter().enumerate() {
+        if opens_group(command_parts, i) {
+            match command_part.as_str() {
+                "for" => check_for(&command_parts[i + 1..])?,
+                "case" => check_case(&command_parts[i + 1..])?,
+                _ => (),
+            }
+            open.push(command_part);
+            continue;
+        }
+        // a case is between clauses, or in the commands of one after its patterns
+        if is_case_pattern_end(command_parts, i) || command_part == ";;" {
+            let (expected, next) = match command_part.as_str() {
+                ")" => ("case", "case)"),
+                _ => ("case)", "case"),
+            };
+            if open.last() != Some(&expected) {
+                return Err(format!("unexpected token `{}'", command_part));
+            }
+            open.pop();
+            open.push(next);
+            continue;
+        }
+        if command_part == "esac" && closes_group(command_parts, i) {
+            if !open.last().is_some_and(|top| top.starts_with("case")) {
+                return Err(format!("unexpected token `{}'", command_part));
+            }
+            open.pop();
+            continue;
+        }
+        if command_part != ")" && !is_command_start(command_parts, i) {
+            continue;
+        }
+        let expected: &[&str] = match command_part.as_str() {
+            ")" => &["("],
+            "}" => &["{"],
+            "then" => &["if", "elif"],
+            "elif" | "else" => &["then"],
+            "fi" => &["then", "else"],
+            "do" => &["while", "until", "for"],
+            "done" => &["do"],
+            _ => continue,
+        };
+        if !open.last().is_some_and(|top| expected.contains(top)) {
+            return Err(format!("unexpected token `{}'", command_part));
+        }
+        // nothing since the last keyword, like `{ }` or `then fi`
+        let empty = opens_group(command_parts, i - 1)
+            || matches!(
+                command_parts[i - 1].as_str(),
+                "then" | "elif" | "else" | "do"
+            ) && is_command_start(command_parts, i - 1);
+        if empty {
+            return Err(format!("unexpected token `{}'", command_part));
+        }
+        open.pop();
+        if !closes_group(command_parts, i) {
+            open.push(command_part);
+        }
+    }
+    return Ok(());
+}
+
+/** The start of a for loop after the `for` is a name, then `in` and the words, or just `;` or `do` */
+fn check_for(command_parts: &[String]) -> Result<(), String> {
+    let name = match command_parts.first() {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    if !is_name(name) {
+        return Err(format!("`{}': not a valid identifier", name));
+    }
+    return match command_parts.get(1).map(|cp| cp.as_str()) {
+        None | Some("in" | ";" | "do") => Ok(()),
+        Some(word) => Err(format!("unexpected token `{}'", word)),
+    };
+}
+
+/** The start of a case after the `case` is the word to match, then `in` */
+fn check_case(command_parts: &[String]) -> Result<(), String> {
+    return match command_parts.get(1).map(|cp| cp.as_str()) {
+        None | Some("in") => Ok(()),
+        Some(word) => Err(format!("unexpected token `{}'", word)),
+    };
+}
+
+/** How many groups deep each word is, counting the parentheses or braces themselves as inside. Operators only apply to
+the level they're at, so `(a; b) | c` is a pipe and the `;` belongs to the subshell */
+fn depths(command_parts: &[String]) -> Vec<usize> {
+    let mut depths = Vec::new();
+    let mut depth: usize = 0;
+    for i in 0..command_parts.len() {
+        if opens_group(command_parts, i) {
+            depth += 1;
+        }
+        depths.push(depth);
+        if closes_group(command_parts, i) {
+            depth = depth.saturating_sub(1);
+        }
+    }
+    return depths;
+}
+
+/** The last word outside of any group that matches */
+fn top_level_rposition(command_parts: &[String], matches: impl Fn(&str) -> bool) -> Option<usize> {
+    let depths = depths(command_parts);
+    return (0..command_parts.len())
+        .rev()
+        .find(|i| depths[*i] == 0 && matches(&command_parts[*i]));
+}
+
+/** Commands separated by `;` run one after another, and a command ending in `&` runs in the background. An empty command after the last separator is fine, like `echo a;` */
+fn parse_list(command_parts: &mut Vec<String>, heredocs: &mut VecDeque<String>) -> Option<Command> {
+    let mut list: Option<Command> = None;
+    let mut start = 0;
+    let depths = depths(command_parts);
+    for end in 0..=command_parts.len() {
+        let separator = command_parts.get(end).map(|cp| cp.as_str());
+        if !matches!(separator, None | Some(";" | "&")) || depths.get(end) > Some(&0) {
+            continue;
+        }
+        let segment = &command_parts[start..end];
+        start = end + 1;
+
+        let mut command = match parse_and_or(&mut segment.to_vec(), heredocs) {
+            Some(command) => command,
+            None => continue,
+        };
+        if separator == Some("&") {
+            command = Command::Background(Box::new(command), segment.join(" "));
+        }
+        list = Some(match list {
+            Some(list) => Command::Sequence(Box::new(list), Box::new(command)),
+            None => command,
+        });
+    }
+    return list;
+}
+
+/** `&&` and `||` have the same precedence and group from the left, so `a || b && c` is `(a || b) && c` */
+fn parse_and_or(
+    command_parts: &mut Vec<String>,
+    heredocs: &mut VecDeque<String>,
+) -> Option<Command> {
+    let operator_index = top_level_rposition(command_parts, |cp| cp == "&&" || cp == "||");
+    if operator_index.is_some() {
+        let (left, right) = command_parts.split_at(operator_index.unwrap());
+        let left_command =
+            Box::new(parse_and_or(&mut left.iter().cloned().collect(), heredocs).unwrap());
+        let right_command =
+            Box::new(parse_redirect(&mut right[1..].iter().cloned().collect(), heredocs).unwrap());
+        return Some(match right[0].as_str() {
+            "&&" => Command::And(left_command, right_command),
+            _ => Command::Or(left_command, right_command),
+        });
+    }
+
+    return parse_redirect(command_parts, heredocs);
+}
+
+/** Takes the redirects out of the command, keeping them in order since each one can depend on the ones before, like
+`> file 2>&1` */
+fn parse_redirect(
+    command_parts: &mut Vec<String>,
+    heredocs: &mut VecDeque<String>,
+) -> Option<Command> {
+    // `time` is a keyword rather than a command, so it can time a whole pipeline with its redirects, like
+    // `time sort big | uniq > out`
+    if command_parts.first().is_some_and(|cp| cp == "time") {
+        command_parts.remove(0);
+        let command =
+            parse_redirect(command_parts, heredocs).unwrap_or(Command::Simple(Vec::new()));
+        return Some(Command::Time(Box::new(command)));
+    }
+    let mut redirects = Vec::new();
     let mut keep = Vec::new();
-    let mut keep_next = true;
+    let mut skip_next = false;
+    let depths = depths(command_parts);
     for (i, command_part) in command_parts.iter().enumerate() {
+        if skip_next {
+            keep.push(false);
+            skip_next = false;
+            continue;
+        }
+        // redirects inside a group are for the command in there
+        if depths[i] > 0 {
+            keep.push(true);
+            continue;
+        }
         let next = command_parts.get(i + 1);
-        match command_part.as_str() {
-            ">" | "1>" => out_path = RedirectType::Truncate(next.unwrap().clone()),
-            ">>" | "1>>" => out_path = RedirectType::Append(next.unwrap().clone()),
-            "2>" => err_path = RedirectType::Truncate(next.unwrap().clone()),
-            "2>>" => err_path = RedirectType::Append(next.unwrap().clone()),
-            _ => {
-                keep.push(keep_next);
-                keep_next = true;
+        if let Some((delimiter, _, uses_next)) = heredoc_operator(command_part, next) {
+            // quoting any part of the delimiter keeps the body literal
+            let expand = !delimiter.contains(['\'', '"', '\\']);
+            let body = heredocs.pop_front().unwrap_or_default();
+            redirects.push((0, RedirectType::Heredoc(body, expand)));
+            keep.push(false);
+            skip_next = uses_next;
+            continue;
+        }
+        if let Some(word) = command_part.strip_prefix("<<<") {
+            if word.is_empty() {
+                redirects.push((0, RedirectType::HereString(next.unwrap().clone())));
+                skip_next = true;
+            } else {
+                redirects.push((0, RedirectType::HereString(word.to_string())));
+            }
+            keep.push(false);
+            continue;
+        }
+        let (fd, operator, target) = match redirect_operator(command_part) {
+            Some(redirect) => redirect,
+            None => {
+                keep.push(true);
                 continue;
             }
+        };
+        // the target can be attached, like `2>/dev/null`, otherwise it's the next word
+        let target = match target.is_empty() {
+            true => {
+                skip_next = true;
+                next.unwrap().clone()
+            }
+            false => target.to_string(),
+        };
+        let redirect = match operator {
+            ">>" | "&>>" => RedirectType::Append(target),
+            ">&" => RedirectType::Duplicate(target),
+            ">|" => RedirectType::Clobber(target),
+            _ => RedirectType::Truncate(target),
+        };
+        redirects.push((fd, redirect));
+        // stderr is a copy of stdout rather than opening the file again, so both streams share one handle and
+        // interleave instead of overwriting each other
+        if operator.starts_with('&') {
+            redirects.push((2, RedirectType::Duplicate("1".to_string())));
         }
         keep.push(false);
-        keep_next = false;
     }
     let mut keep_iter = keep.iter();
     command_parts.retain(|_| *keep_iter.next().unwrap());
 
-    let command = parse_pipe(command_parts);
+    let command = parse_pipe(command_parts, heredocs);
 
-    if out_path.is_some() || err_path.is_some() {
-        return Some(Command::Redirect(
-            out_path,
-            err_path,
-            Box::new(command.unwrap()),
-        ));
+    if !redirects.is_empty() {
+        return Some(Command::Redirect(redirects, Box::new(command.unwrap())));
     }
     return command;
 }
 
-fn parse_pipe(command_parts: &mut Vec<String>) -> Option<Command> {
-    let pipe_index = command_parts.iter().rposition(|cp| cp == "|");
+/** The redirect operator at the start of the word, like `>`, `2>>`, or `3>&`, as the file descriptor it's for (stdout
+unless it's given), the operator, and anything after it in the same word. `&>` and `&>>` are for stdout and stderr
+together */
+fn redirect_operator(word: &str) -> Option<(i32, &str, &str)> {
+    if let Some(operator) = ["&>>", "&>"]
+        .into_iter()
+        .find(|operator| word.starts_with(operator))
+    {
+        return Some((1, operator, &word[operator.len()..]));
+    }
+    let (fd, rest) = match word.chars().next() {
+        Some(digit @ '0'..='9') => (digit as i32 - '0' as i32, &word[1..]),
+        _ => (1, word),
+    };
+    for operator in [">>", ">&", ">|", ">"] {
+        if let Some(target) = rest.strip_prefix(operator) {
+            return Some((fd, operator, target));
+        }
+    }
+    return None;
+}
+
+/** `&&`, `||`, and `|` join two commands, so `&& ls`, `ls | | wc`, and `(ls &&)` are errors. One at the end of the
+input never gets here, the tokenizer reads on past the newline for the command after it */
+fn check_operators(command_parts: &[String]) -> Result<(), String> {
+    for (i, command_part) in command_parts.iter().enumerate() {
+        if !joins_commands(command_part) {
+            continue;
+        }
+        if is_command_start(command_parts, i) {
+            return Err(format!("unexpected token `{}'", command_part));
+        }
+        match command_parts.get(i + 1).map(|cp| cp.as_str()) {
+            None => return Err("unexpected token `newline'".to_string()),
+            Some(next @ (";" | ";;" | "&" | ")")) => {
+                return Err(format!("unexpected token `{}'", next))
+            }
+            _ => (),
+        }
+    }
+    return Ok(());
+}
+
+/** If the word is an operator that needs a command on both sides */
+fn joins_commands(word: &str) -> bool {
+    return matches!(word, "&&" | "||" | "|");
+}
+
+/** Every redirect has something to redirect to, so `echo >`, `echo > | cat`, and `cat <<<` are errors */
+fn check_redirects(command_parts: &[String]) -> Result<(), String> {
+    for (i, command_part) in command_parts.iter().enumerate() {
+        let target = match redirect_operator(command_part) {
+            Some((_, _, target)) => target,
+            None => match command_part.strip_prefix("<<<") {
+                Some(word) => word,
+                None => continue,
+            },
+        };
+        if !target.is_empty() {
+            continue;
+        }
+        return match command_parts.get(i + 1).map(|cp| cp.as_str()) {
+            None => Err("unexpected token `newline'".to_string()),
+            Some(next @ (";" | ";;" | "&" | "|" | "&&" | "||" | "(" | ")")) => {
+                Err(format!("unexpected token `{}'", next))
+            }
+            Some(_) => continue,
+        };
+    }
+    return Ok(());
+}
+
+fn parse_pipe(command_parts: &mut Vec<String>, heredocs: &mut VecDeque<String>) -> Option<Command> {
+    let pipe_index = top_level_rposition(command_parts, |cp| cp == "|");
     if pipe_index.is_some() {
         let (left, right) = command_parts.split_at(pipe_index.unwrap());
-        let left_command = parse_pipe(&mut left.iter().cloned().collect()).unwrap();
-        let right_command = parse_command(&right[1..].iter().cloned().collect()).unwrap();
+        let left_command = parse_pipe(&mut left.iter().cloned().collect(), heredocs).unwrap();
+        let right_command = parse_simple(&right[1..].iter().cloned().collect(), heredocs).unwrap();
         return Some(Command::Pipe(
             Box::new(left_command),
             Box::new(right_command),
         ));
     }
 
-    return parse_command(command_parts);
+    return parse_simple(command_parts, heredocs);
 }
 
-fn parse_command(command_parts: &Vec<String>) -> Option<Command> {
+/** A simple command keeps its words unexpanded until it runs, since expansions depend on the shell's state at that point.
+Words in parentheses are a subshell, in braces a group, between `if` and `fi` a conditional, and so on */
+fn parse_simple(command_parts: &Vec<String>, heredocs: &mut VecDeque<String>) -> Option<Command> {
     if command_parts.len() < 1 {
         return None;
     }
+    if command_parts[0] == "(" && command_parts.last().map(|cp| cp.as_str()) == Some(")") {
+        let mut inner = command_parts[1..command_parts.len() - 1].to_vec();
+        let inner = parse_list(&mut inner, heredocs)?;
+        return Some(Command::Subshell(Box::new(inner)));
+    }
+    if command_parts[0] == "{" && closes_group(command_parts, command_parts.len() - 1) {
+        let mut inner = command_parts[1..command_parts.len() - 1].to_vec();
+        let inner = parse_list(&mut inner, heredocs)?;
+        return Some(Command::Group(Box::new(inner)));
+    }
+    if command_parts[0] == "if" && closes_group(command_parts, command_parts.len() - 1) {
+        return parse_if(&command_parts[1..command_parts.len() - 1], heredocs);
+    }
+    if command_parts[0] == "case" && closes_group(command_parts, command_parts.len() - 1) {
+        return parse_case(command_parts, heredocs);
+    }
+    if command_parts[0] == "for" && closes_group(command_parts, command_parts.len() - 1) {
+        return parse_for(&command_parts[1..command_parts.len() - 1], heredocs);
+    }
+    if matches!(command_parts[0].as_str(), "while" | "until")
+        && closes_group(command_parts, command_parts.len() - 1)
+    {
+        let until = command_parts[0] == "until";
+        return parse_while(until, &command_parts[1..command_parts.len() - 1], heredocs);
+    }
+    return Some(Command::Simple(command_parts.clone()));
+}
 
-    return Some(match command_parts[0].as_str() {
-        "exit" => Command::Exit, // might need the input later to change the exit code
-        "echo" => Command::Echo(command_parts[1..].iter().cloned().collect()),
-        "type" => Command::Type(
-            command_parts[1..]
-                .iter()
-                .map(|cp| parse_command(&vec![cp.clone()]).unwrap())
-                .collect(),
-        ),
-        "pwd" => Command::PWD,
-        "cd" => Command::CD(command_parts[1..].iter().cloned().collect()),
-        _ => {
-            let paths = env::var_os("PATH").unwrap();
-            let mut found_command = None;
-            for path in env::split_paths(&paths) {
-                let exec_path = path.join(command_parts[0].as_str());
-                if exec_path.is_file() {
-                    found_command = Some(Command::Executable(
-                        exec_path,
-                        command_parts[1..].iter().cloned().collect(),
-                    ));
-                    break;
-                }
+/** The words between `if` and `fi`. An `elif` is another if in the else branch. check_groups has already made sure the
+keywords are in order */
+fn parse_if(command_parts: &[String], heredocs: &mut VecDeque<String>) -> Option<Command> {
+    let depths = depths(command_parts);
+    let is_keyword = |i: usize, keywords: &[&str]| {
+        depths[i] == 0
+            && is_command_start(command_parts, i)
+            && keywords.contains(&command_parts[i].as_str())
+    };
+    let then_index = (0..command_parts.len()).find(|i| is_keyword(*i, &["then"]))?;
+    let else_index = (then_index..command_parts.len()).find(|i| is_keyword(*i, &["elif", "else"]));
+
+    let condition = parse_list(&mut command_parts[..then_index].to_vec(), heredocs)?;
+    let then_end = else_index.unwrap_or(command_parts.len());
+    let then_command = parse_list(
+        &mut command_parts[then_index + 1..then_end].to_vec(),
+        heredocs,
+    )?;
+    let else_command = match else_index {
+        Some(i) if command_parts[i] == "elif" => Some(parse_if(&command_parts[i + 1..], heredocs)?),
+        Some(i) => Some(parse_list(&mut command_parts[i + 1..].to_vec(), heredocs)?),
+        None => None,
+    };
+    return Some(Command::If(
+        Box::new(condition),
+        Box::new(then_command),
+        else_command.map(Box::new),
+    ));
+}
+
+/** The words between `while` (or `until`) and `done`, split on the `do` */
+fn parse_while(
+    until: bool,
+    command_parts: &[String],
+    heredocs: &mut VecDeque<String>,
+) -> Option<Command> {
+    let depths = depths(command_parts);
+    let do_index = (0..command_parts.len()).find(|i| {
+        depths[*i] == 0 && is_command_start(command_parts, *i) && command_parts[*i] == "do"
+    })?;
+    let condition = parse_list(&mut command_parts[..do_index].to_vec(), heredocs)?;
+    let body = parse_list(&mut command_parts[do_index + 1..].to_vec(), heredocs)?;
+    return Some(Command::While(until, Box::new(condition), Box::new(body)));
+}
+
+/** The words between `for` and `done`: the name, `in` and the words to loop over, then `do` and the body. Without `in`
+it loops over the positional parameters */
+fn parse_for(command_parts: &[String], heredocs: &mut VecDeque<String>) -> Option<Command> {
+    let depths = depths(command_parts);
+    let do_index = (0..command_parts.len()).find(|i| {
+        depths[*i] == 0 && is_command_start(command_parts, *i) && command_parts[*i] == "do"
+    })?;
+    let name = command_parts[0].clone();
+    let mut words = match command_parts.get(1).map(|cp| cp.as_str()) {
+        Some("in") => command_parts[2..do_index].to_vec(),
+        _ => vec!["\"$@\"".to_string()],
+    };
+    if words.last().map(|word| word.as_str()) == Some(";") {
+        words.pop();
+    }
+    let body = parse_list(&mut command_parts[do_index + 1..].to_vec(), heredocs)?;
+    return Some(Command::For(name, words, Box::new(body)));
+}
+
+/** The words from `case` to `esac`. Each clause is its patterns, and the commands up to the `;;` (which the last clause
+can leave out). check_groups has already made sure the clauses are in order */
+fn parse_case(command_parts: &[String], heredocs: &mut VecDeque<String>) -> Option<Command> {
+    let depths = depths(command_parts);
+    let word = command_parts.get(1)?.clone();
+    let end = command_parts.len() - 1;
+    let mut clauses = Vec::new();
+    let mut i = 3;
+    while i < end {
+        if command_parts[i] == ";" {
+            i += 1;
+            continue;
+        }
+        let pattern_end = (i..end).find(|j| is_case_pattern_end(command_parts, *j))?;
+        let mut patterns = Vec::new();
+        for pattern in &command_parts[i..pattern_end] {
+            if pattern != "(" && pattern != "|" {
+                patterns.extend(split_patterns(pattern));
+            }
+        }
+        let body_end = (pattern_end..end)
+            .find(|j| depths[*j] == 1 && command_parts[*j] == ";;")
+            .unwrap_or(end);
+        let body = parse_list(
+            &mut command_parts[pattern_end + 1..body_end].to_vec(),
+            heredocs,
+        );
+        clauses.push((patterns, body));
+        i = body_end + 1;
+    }
+    return Some(Command::Case(word, clauses));
+}
+
+/** Splits `a|b` into its patterns. A quoted or escaped `|` is part of the pattern */
+fn split_patterns(word: &str) -> Vec<String> {
+    let mut patterns = vec![String::new()];
+    let mut quote_state = QuoteState::None;
+    let mut chars = word.chars();
+    while let Some(char) = chars.next() {
+        match (quote_state, char) {
+            (QuoteState::None, '|') => {
+                patterns.push(String::new());
+                continue;
             }
-            found_command.unwrap_or_else(|| Command::InvalidCommand(command_parts[0].clone()))
+            (QuoteState::None, '\\') | (QuoteState::Double, '\\') => {
+                let pattern = patterns.last_mut().unwrap();
+                pattern.push(char);
+                pattern.extend(chars.next());
+                continue;
+            }
+            (QuoteState::None, '\'') => quote_state = QuoteState::Single,
+            (QuoteState::None, '"') => quote_state = QuoteState::Double,
+            (QuoteState::Single, '\'') | (QuoteState::Double, '"') => {
+                quote_state = QuoteState::None
+            }
+            _ => (),
         }
+        patterns.last_mut().unwrap().push(char);
+    }
+    return patterns;
+}
+
+/** Turns expanded words into the command they name */
+pub fn parse_command(command_parts: &Vec<String>, shell: &mut Shell) -> Option<Command> {
+    if command_parts.len() < 1 {
+        return None;
+    }
+
+    let name = command_parts[0].as_str();
+    // env with arguments runs a command, which is left to the real env
+    if builtins::find(name).is_none() || name == "env" && command_parts.len() > 1 {
+        return Some(parse_program(command_parts, shell));
+    }
+
+    // every name in the builtins table has to be handled here
+    return Some(match name {
+        "exit" => Command::Exit(command_parts[1..].iter().cloned().collect()),
+        "echo" => Command::Echo(command_parts[1..].iter().cloned().collect()),
+        "type" => {
+            let all = command_parts.get(1).map(|cp| cp == "-a").unwrap_or(false);
+            let names = if all {
+                &command_parts[2..]
+            } else {
+                &command_parts[1..]
+            };
+            Command::Type(
+                all,
+                names
+                    .iter()
+                    .map(|cp| parse_command(&vec![cp.clone()], shell).unwrap())
+                    .collect(),
+            )
+        }
+        "pwd" => Command::PWD(command_parts[1..].iter().cloned().collect()),
+        "cd" => Command::CD(command_parts[1..].iter().cloned().collect()),
+        "history" => Command::History(command_parts[1..].iter().cloned().collect()),
+        "printf" => Command::Printf(command_parts[1..].iter().cloned().collect()),
+        "source" | "." => Command::Source(command_parts[1..].iter().cloned().collect()),
+        "set" => Command::Set(command_parts[1..].iter().cloned().collect()),
+        "shift" => Command::Shift(command_parts[1..].iter().cloned().collect()),
+        "eval" => Command::Eval(command_parts[1..].iter().cloned().collect()),
+        "true" => Command::True,
+        "false" => Command::False,
+        "test" => Command::Test(false, command_parts[1..].iter().cloned().collect()),
+        "kill" => Command::Kill(command_parts[1..].iter().cloned().collect()),
+        "jobs" => Command::Jobs(command_parts[1..].iter().cloned().collect()),
+        "fg" => Command::Fg(command_parts[1..].iter().cloned().collect()),
+        "bg" => Command::Bg(command_parts[1..].iter().cloned().collect()),
+        "read" => Command::Read(command_parts[1..].iter().cloned().collect()),
+        "umask" => Command::Umask(command_parts[1..].iter().cloned().collect()),
+        "pushd" => Command::Pushd(command_parts[1..].iter().cloned().collect()),
+        "popd" => Command::Popd(command_parts[1..].iter().cloned().collect()),
+        "dirs" => Command::Dirs(command_parts[1..].iter().cloned().collect()),
+        "env" => Command::Env,
+        "printenv" => Command::Printenv(command_parts[1..].iter().cloned().collect()),
+        "declare" => Command::Declare(command_parts[1..].iter().cloned().collect()),
+        "export" => Command::Export(command_parts[1..].iter().cloned().collect()),
+        "unset" => Command::Unset(command_parts[1..].iter().cloned().collect()),
+        "help" => Command::Help(command_parts[1..].iter().cloned().collect()),
+        "hash" => Command::Hash(command_parts[1..].iter().cloned().collect()),
+        "command" => Command::CommandBuiltin(command_parts[1..].iter().cloned().collect()),
+        "which" => Command::Which(command_parts[1..].iter().cloned().collect()),
+        "timeout" => Command::Timeout(command_parts[1..].iter().cloned().collect()),
+        "trap" => Command::Trap(command_parts[1..].iter().cloned().collect()),
+        "[" => Command::Test(true, command_parts[1..].iter().cloned().collect()),
+        "alias" => Command::Alias(command_parts[1..].iter().cloned().collect()),
+        "unalias" => Command::Unalias(command_parts[1..].iter().cloned().collect()),
+        _ => unreachable!("the builtin {} isn't parsed", name),
     });
 }
 
+/** The program the words run, which is either a path (with a /) or a name found on PATH */
+fn parse_program(command_parts: &Vec<String>, shell: &mut Shell) -> Command {
+    return match command_parts[0].as_str() {
+        // a name with a / is the path to run, PATH isn't searched. Whether it can run is found out by running it
+        name if name.contains('/') => match Path::new(name).exists() {
+            true => Command::Executable(
+                name.to_string(),
+                PathBuf::from(name),
+                command_parts[1..].iter().cloned().collect(),
+            ),
+            false => Command::InvalidCommand(name.to_string()),
+        },
+        name => match shell.hash_table.find(name) {
+            Some(exec_path) => Command::Executable(
+                name.to_string(),
+                exec_path,
+                command_parts[1..].iter().cloned().collect(),
+            ),
+            None => Command::InvalidCommand(name.to_string()),
+        },
+    };
+}
+
+/** Every executable file with the name in the PATH directories, in PATH order. Files without an execute bit are
+skipped, so one later in PATH can still run. A name with a / is a path, which is never searched for */
+pub fn find_executables(name: &str) -> Vec<PathBuf> {
+    if name.contains('/') {
+        return Vec::new();
+    }
+    let paths = env::var_os("PATH").unwrap_or_default();
+    return env::split_paths(&paths)
+        .map(|path| path.join(name))
+        .filter(|exec_path| is_executable(exec_path))
+        .collect();
+}
+
+/** A file (or symlink to one) with any execute bit set */
+pub fn is_executable(path: &Path) -> bool {
+    return match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    };
+}
+
+/** If the string is a valid variable name (letters, digits, and underscores, not starting with a digit) */
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    return match chars.next() {
+        Some(first) if first == '_' || first.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    };
+}
+
+/** Splits a `NAME=value` word into the name and the (unexpanded) value, if it's an assignment */
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    return is_name(name).then_some((name, value));
+}
+
 #[derive(Debug)]
 pub enum RedirectType {
-    None,
     Truncate(String),
+    /** `>|`, truncates even with noclobber on */
+    Clobber(String),
     Append(String),
+    /** `>&n`, a copy of another file descriptor */
+    Duplicate(String),
+    /** The body, and whether to expand parameters in it */
+    Heredoc(String, bool),
+    /** `<<< word`, the word is expanded and given a trailing newline */
+    HereString(String),
 }
 
 impl RedirectType {
-    pub fn is_some(&self) -> bool {
-        match self {
-            RedirectType::None => false,
-            _ => true,
-        }
-    }
-
-    pub fn as_io(&self) -> IO {
-        match self {
-            RedirectType::None => IO::Default,
-            RedirectType::Truncate(path) => IO::File(File::create(path).unwrap()),
-            RedirectType::Append(path) => IO::File(
-                OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(path)
-                    .unwrap(),
-            ),
+    /** Opens what's redirected to, given the file descriptors open so far. The error is the message to show if it
+    can't be */
+    pub async fn as_io(&self, fds: &BTreeMap<i32, IO>, shell: &mut Shell) -> Result<IO, String> {
+        return Ok(match self {
+            RedirectType::Truncate(path)
+            | RedirectType::Clobber(path)
+            | RedirectType::Append(path) => {
+                let path = target(path, shell).await?;
+                let append = matches!(self, RedirectType::Append(..));
+                // only regular files are protected, writing to something like /dev/null is still fine
+                let protected =
+                    shell.options.noclobber && matches!(self, RedirectType::Truncate(..));
+                if protected && fs::metadata(&path).is_ok_and(|metadata| metadata.is_file()) {
+                    return Err(format!("{}: cannot overwrite existing file", path));
+                }
+                // only asked when someone is there to answer, a script goes ahead
+                let confirm = shell.options.confirm_overwrite
+                    && matches!(self, RedirectType::Truncate(..))
+                    && io::stdin().is_terminal();
+                if confirm
+                    && fs::metadata(&path)
+                        .is_ok_and(|metadata| metadata.is_file() && metadata.len() > 0)
+                {
+                    let answer = shell
+                        .editor
+                        .readline(&format!("overwrite {}? (y/n) ", path))
+                        .unwrap_or_default();
+                    if !answer.trim_start().starts_with(['y', 'Y']) {
+                        return Err(format!("{}: not overwritten", path));
+                    }
+                }
+                let file = open_output(&path, append, shell.options.mkdir_redirect)?;
+                match path == "/dev/null" {
+                    true => IO::Null(file),
+                    false => IO::File(file),
+                }
+            }
+            RedirectType::Duplicate(word) => {
+                let word = expand::expand_value(word, shell).await;
+                let fd = match word.parse::<i32>() {
+                    Ok(fd) => fd,
+                    Err(_) => return Err(format!("{}: ambiguous redirect", word)),
+                };
+                match fds.get(&fd) {
+                    Some(io) => io.clone(),
+                    None => return Err(format!("{}: Bad file descriptor", fd)),
+                }
+            }
+            RedirectType::Heredoc(body, expand) => {
+                let body = if *expand {
+                    expand::expand_heredoc(body, shell).await
+                } else {
+                    body.clone()
+                };
+                IO::from_bytes(body.into_bytes())
+            }
+            RedirectType::HereString(word) => {
+                let content = expand::expand_word(word, shell).await.join(" ") + "\n";
+                IO::from_bytes(content.into_bytes())
+            }
+        });
+    }
+}
+
+/** The file a redirect's word names. Quoted and escaped spaces are part of the name, like `> "my file"`, but a word
+that expands to several words, like `> $files`, doesn't name one file */
+async fn target(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut fields = expand::expand_word(word, shell).await;
+    if fields.len() != 1 {
+        return Err(format!("{}: ambiguous redirect", word));
+    }
+    return Ok(fields.remove(0));
+}
+
+/** Opens a file to redirect output to, creating it if it doesn't exist. Missing directories on the way to it are only
+created if asked for, like with `set -o mkdir-redirect` */
+fn open_output(path: &str, append: bool, create_dirs: bool) -> Result<File, String> {
+    let parent = Path::new(path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    let missing_parent = parent.is_some_and(|parent| !parent.exists());
+    if missing_parent && create_dirs {
+        if let Err(error) = fs::create_dir_all(parent.unwrap()) {
+            return Err(format!("{}: {}", path, error_reason(&error)));
         }
     }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path);
+    return match file {
+        Ok(file) => Ok(file),
+        // it's off by default like in other shells, but worth knowing about when it's what went wrong
+        Err(error) if missing_parent && !create_dirs => Err(format!(
+            "{}: {} (set -o mkdir-redirect to create missing directories)",
+            path,
+            error_reason(&error)
+        )),
+        Err(error) => Err(format!("{}: {}", path, error_reason(&error))),
+    };
 }
 
-#[derive(PartialEq)]
-enum QuoteState {
+/** The reason part of an io error, like `No such file or directory`, without the `(os error 2)` */
+fn error_reason(error: &std::io::Error) -> String {
+    let reason = error.to_string();
+    return match reason.find(" (os error") {
+        Some(end) => reason[..end].to_string(),
+        None => reason,
+    };
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum QuoteState {
     None,
     Single,
     Double,
 }
 
-pub fn transform_input(input: &str) -> Vec<String> {
-    let home = env::var_os("HOME").unwrap();
+/** Splits the input into words on unquoted whitespace. Quotes and escapes are kept so the words can be expanded when the command runs.
+Quoted and unquoted parts with nothing between them, like `a'b'"c"`, are one word.
+The error is for a line that ends inside quotes or right after a backslash, which would otherwise run with mangled words */
+pub fn transform_input(input: &str) -> Result<Vec<String>, String> {
+    return tokenize(input, false).map(|(output, _)| output);
+}
 
+/** Splits the words of the command line at the start of the input, which ends at the first newline outside of quotes.
+Also gives the lines after it, if there are any */
+fn split_command_line(input: &str) -> Result<(Vec<String>, Option<&str>), String> {
+    return tokenize(input, true);
+}
+
+/** A `\` before a newline joins the lines. With stop_at_newline, an unquoted newline ends the input and what's after it is given back */
+fn tokenize(input: &str, stop_at_newline: bool) -> Result<(Vec<String>, Option<&str>), String> {
     let mut output: Vec<String> = Vec::new();
     let mut current_string = String::new();
     let mut quote_state = QuoteState::None;
     let mut escaped = false;
+    let mut dollar = false;
+    let mut rest = None;
+    let mut depth: usize = 0;
+
+    // only the start is trimmed, whitespace at the end could be escaped (`a\ `), and otherwise ends the word anyway
+    let mut chars = input.trim_start().chars();
+    while let Some(char) = chars.next() {
+        // an escaped space or tab is part of the word, not the end of it
+        if escaped {
+            if char == '\n' {
+                // the backslash was kept for expansion, but a line continuation leaves nothing behind
+                current_string.pop();
+                escaped = false;
+                continue;
+            }
+            current_string.push(char);
+            escaped = false;
+            continue;
+        }
+        // a command substitution stays in the word whole, whatever spaces and quotes it has inside
+        if dollar && char == '(' && quote_state != QuoteState::Single {
+            current_string.push('(');
+            match read_substitution(&mut chars) {
+                Some(command) => current_string += &command,
+                None => {
+                    return Err("unexpected end of file while looking for matching `)'".to_string())
+                }
+            }
+            current_string.push(')');
+            dollar = false;
+            continue;
+        }
+        dollar = char == '$' && quote_state != QuoteState::Single;
 
-    for char in input.trim().chars() {
         match quote_state {
             QuoteState::None => {
-                if escaped {
-                    current_string.push(char);
-                    escaped = false;
-                    continue;
+                if char == '\n' && stop_at_newline {
+                    // the word before could be what opens or closes a group
+                    push_word(&mut output, &mut current_string, &mut depth);
+                    // inside a group a newline separates commands like ;, so a subshell or { } can go over several lines
+                    if depth > 0 {
+                        output.push(";".to_string());
+                        continue;
+                    }
+                    // and after `&&`, `||`, or `|` the command they're waiting for is on the next line
+                    if output.last().is_some_and(|word| joins_commands(word)) {
+                        continue;
+                    }
+                    rest = Some(chars.as_str());
+                    break;
                 }
-
                 if char::is_ascii_whitespace(&char) {
-                    if current_string.len() > 0 {
-                        output.push(current_string);
-                        current_string = String::new();
+                    push_word(&mut output, &mut current_string, &mut depth);
+                    continue;
+                }
+                // a # that starts a word comments out the rest of the line, one inside a word like `a#b` is literal
+                if char == '#' && current_string.is_empty() {
+                    let comment = chars.as_str();
+                    chars = comment[comment.find('\n').unwrap_or(comment.len())..].chars();
+                    continue;
+                }
+                // ; ends a command even without spaces around it, like `cd /tmp; ls`, and so do parentheses
+                if matches!(char, ';' | '(' | ')') {
+                    push_word(&mut output, &mut current_string, &mut depth);
+                    current_string.push(char);
+                    // `;;` ends a case clause
+                    if char == ';' && chars.as_str().starts_with(';') {
+                        current_string.push(chars.next().unwrap());
                     }
+                    push_word(&mut output, &mut current_string, &mut depth);
                     continue;
                 }
 
                 match char {
                     '\'' => quote_state = QuoteState::Single,
                     '"' => quote_state = QuoteState::Double,
-                    '~' => current_string.push_str(home.to_str().unwrap()),
                     '\\' => escaped = true,
-                    _ => current_string.push(char),
+                    _ => (),
                 }
             }
             QuoteState::Single => {
                 if char == '\'' {
                     quote_state = QuoteState::None;
-                } else {
-                    current_string.push(char);
                 }
             }
-            QuoteState::Double => {
-                if escaped {
-                    match char {
-                        // fallthrough to adding the char
-                        '"' | '\\' => (),
-                        // need to add the \ because it didn't escape anything
-                        _ => current_string.push('\\'),
-                    }
-                    current_string.push(char);
-                    escaped = false;
-                    continue;
-                }
+            QuoteState::Double => match char {
+                '"' => quote_state = QuoteState::None,
+                '\\' => escaped = true,
+                _ => (),
+            },
+        }
+        current_string.push(char);
+    }
+    if quote_state != QuoteState::None {
+        return Err("unterminated quoted string".to_string());
+    }
+    if escaped {
+        return Err("unexpected end of line after \\".to_string());
+    }
+    push_word(&mut output, &mut current_string, &mut depth);
+    let unfinished = depth > 0 || output.last().is_some_and(|word| joins_commands(word));
+    if unfinished && stop_at_newline {
+        return Err("unexpected end of file".to_string());
+    }
+    return Ok((output, rest));
+}
 
-                match char {
-                    '"' => quote_state = QuoteState::None,
-                    '\\' => escaped = true,
-                    _ => current_string.push(char),
+/** Ends the word being built, if there is one, keeping track of how many groups deep the tokenizer is */
+fn push_word(output: &mut Vec<String>, current_string: &mut String, depth: &mut usize) {
+    if current_string.is_empty() {
+        return;
+    }
+    output.push(std::mem::take(current_string));
+    if opens_group(output, output.len() - 1) {
+        *depth += 1;
+    } else if closes_group(output, output.len() - 1) {
+        *depth = depth.saturating_sub(1);
+    }
+}
+
+/** Reads the command of a `$(...)` substitution after the `$(`, through the matching `)` (which isn't included). Parentheses inside quotes or nested substitutions don't end it early. None if the input ends before the `)` */
+pub fn read_substitution(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut command = String::new();
+    let mut depth = 1;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    while let Some(char) = chars.next() {
+        if escaped {
+            escaped = false;
+        } else if quote == Some('\'') {
+            if char == '\'' {
+                quote = None;
+            }
+        } else {
+            match char {
+                '\\' => escaped = true,
+                '\'' | '"' if quote.is_none() => quote = Some(char),
+                '"' => quote = None,
+                '(' if quote.is_none() => depth += 1,
+                ')' if quote.is_none() => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(command);
+                    }
                 }
+                _ => (),
             }
         }
+        command.push(char);
     }
-    if current_string.len() > 0 {
-        output.push(current_string);
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** The syntax error for a command line, if it has one */
+    fn syntax_error(input: &str) -> Option<String> {
+        let (command_parts, _) = match split_command_line(input) {
+            Ok(split) => split,
+            Err(error) => return Some(error),
+        };
+        return check_groups(&command_parts)
+            .and_then(|_| check_operators(&command_parts))
+            .and_then(|_| check_redirects(&command_parts))
+            .err();
+    }
+
+    #[test]
+    fn and_or_need_a_command_on_both_sides() {
+        assert_eq!(syntax_error("true && echo yes"), None);
+        assert_eq!(syntax_error("false || echo no | cat"), None);
+        assert_eq!(syntax_error("&& echo x").unwrap(), "unexpected token `&&'");
+        assert_eq!(syntax_error("|| echo x").unwrap(), "unexpected token `||'");
+        assert_eq!(syntax_error("| cat").unwrap(), "unexpected token `|'");
+        assert_eq!(
+            syntax_error("echo a && && echo b").unwrap(),
+            "unexpected token `&&'"
+        );
+        assert_eq!(
+            syntax_error("echo a; && echo b").unwrap(),
+            "unexpected token `&&'"
+        );
+        assert_eq!(
+            syntax_error("echo a && ; echo b").unwrap(),
+            "unexpected token `;'"
+        );
+        assert_eq!(syntax_error("(echo a &&)").unwrap(), "unexpected token `)'");
+    }
+
+    #[test]
+    fn here_string_needs_a_word() {
+        assert_eq!(syntax_error("cat <<< hello"), None);
+        assert_eq!(syntax_error("cat <<<hello"), None);
+        assert_eq!(
+            syntax_error("cat <<<").unwrap(),
+            "unexpected token `newline'"
+        );
+        assert_eq!(
+            syntax_error("cat <<< ; ls").unwrap(),
+            "unexpected token `;'"
+        );
+        assert_eq!(
+            syntax_error("cat <<< | wc").unwrap(),
+            "unexpected token `|'"
+        );
+    }
+
+    #[test]
+    fn trailing_and_or_continues_on_the_next_line() {
+        assert!(needs_more_lines("echo a &&"));
+        assert!(needs_more_lines("echo a ||"));
+        assert!(needs_more_lines("echo a |"));
+        assert!(!needs_more_lines("echo a &&\necho b"));
+        assert!(!needs_more_lines("echo '&&'"));
+        let (command_parts, _) = split_command_line("echo a &&\necho b").unwrap();
+        assert_eq!(command_parts, ["echo", "a", "&&", "echo", "b"]);
+    }
+
+    #[test]
+    fn every_builtin_is_parsed() {
+        let mut shell = Shell::new();
+        for name in builtins::names() {
+            let command = parse_command(&vec![name.to_string()], &mut shell);
+            assert!(
+                !matches!(
+                    command,
+                    None | Some(Command::Executable(..) | Command::InvalidCommand(..))
+                ),
+                "{} isn't parsed as a builtin",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn adjacent_quoted_parts_are_one_word() {
+        assert_eq!(transform_input("echo a'b'c").unwrap(), ["echo", "a'b'c"]);
+        assert_eq!(
+            transform_input("echo \"a\"'b'").unwrap(),
+            ["echo", "\"a\"'b'"]
+        );
+        assert_eq!(
+            transform_input("echo a\"b\"'c'd").unwrap(),
+            ["echo", "a\"b\"'c'd"]
+        );
+        assert_eq!(
+            transform_input("echo 'a' \"b\"").unwrap(),
+            ["echo", "'a'", "\"b\""]
+        );
+    }
+
+    #[test]
+    fn unfinished_input_needs_more_lines() {
+        for unfinished in [
+            "echo 'abc",
+            "echo \"abc",
+            "echo $(ls",
+            "if true; then echo x",
+            "if true; then echo x; else",
+            "while true; do",
+            "for x in a b; do echo $x",
+            "case x in",
+            "case x in x) echo x;;",
+            "echo a \\",
+            "cat <<EOF\nline",
+        ] {
+            assert!(needs_more_lines(unfinished), "{:?} is finished", unfinished);
+        }
+        for finished in [
+            "echo 'abc'",
+            "echo $(ls)",
+            "if true; then echo x; fi",
+            "while true; do echo x; done",
+            "for x in a b; do echo $x; done",
+            "case x in x) echo x;; esac",
+            "echo a \\\\",
+            "echo if while",
+            "cat <<EOF\nline\nEOF",
+        ] {
+            assert!(!needs_more_lines(finished), "{:?} isn't finished", finished);
+        }
+    }
+
+    #[test]
+    fn redirect_can_name_any_file_descriptor() {
+        assert_eq!(redirect_operator("3>out"), Some((3, ">", "out")));
+        assert_eq!(redirect_operator("3>>out"), Some((3, ">>", "out")));
+        assert_eq!(redirect_operator(">out"), Some((1, ">", "out")));
+        let mut command_parts = vec!["echo".to_string(), "hi".to_string(), "3>out".to_string()];
+        let command = parse_redirect(&mut command_parts, &mut VecDeque::new());
+        assert!(matches!(
+            command,
+            Some(Command::Redirect(redirects, _))
+                if matches!(redirects.as_slice(), [(3, RedirectType::Truncate(target))] if target == "out")
+        ));
+    }
+
+    #[test]
+    fn aliases_expand_through_each_other() {
+        let mut shell = Shell::new();
+        for (name, value) in [
+            ("ll", "ls -l"),
+            ("l", "ll -a"),
+            ("ls", "ls -F"),
+            ("s", "sudo "),
+            ("d", "do "),
+        ] {
+            shell.aliases.insert(name.to_string(), value.to_string());
+        }
+        let expand = |input: &str| expand_aliases(split_command_line(input).unwrap().0, &shell);
+        // chained, and an alias isn't expanded again inside itself
+        assert_eq!(expand("l x"), ["ls", "-F", "-l", "-a", "x"]);
+        // a trailing blank expands the next word too, and that one's blank the word after it
+        assert_eq!(expand("s ll x"), ["sudo", "ls", "-F", "-l", "x"]);
+        assert_eq!(expand("d s ll"), ["do", "sudo", "ls", "-F", "-l"]);
+        // but not without one
+        assert_eq!(expand("echo ll"), ["echo", "ll"]);
+        assert_eq!(expand("l ll"), ["ls", "-F", "-l", "-a", "ll"]);
+    }
+
+    #[test]
+    fn escaped_blanks_and_backslashes_stay_in_the_word() {
+        // outside quotes an escaped space or tab doesn't end the word, but an escaped backslash is done by the blank
+        assert_eq!(transform_input("echo a\\ b").unwrap(), ["echo", "a\\ b"]);
+        assert_eq!(transform_input("echo a\\\tb").unwrap(), ["echo", "a\\\tb"]);
+        assert_eq!(
+            transform_input("echo a\\\\ b").unwrap(),
+            ["echo", "a\\\\", "b"]
+        );
+        assert_eq!(transform_input("echo a\\ ").unwrap(), ["echo", "a\\ "]);
+        // in double quotes \\ is one backslash, so the quote after it still closes them
+        assert_eq!(
+            transform_input("echo \"a\\ b\"").unwrap(),
+            ["echo", "\"a\\ b\""]
+        );
+        assert_eq!(
+            transform_input("echo \"a\\\\\" b").unwrap(),
+            ["echo", "\"a\\\\\"", "b"]
+        );
+        // and in single quotes a backslash is only itself
+        assert_eq!(
+            transform_input("echo 'a\\ b'").unwrap(),
+            ["echo", "'a\\ b'"]
+        );
+        assert_eq!(
+            transform_input("echo 'a\\' b").unwrap(),
+            ["echo", "'a\\'", "b"]
+        );
     }
-    return output;
 }
@@ -0,0 +1,395 @@
+use std::{iter::Peekable, slice::Iter, str::Chars};
+
+/** Formats args printf style. The format is reused while there are args left, like bash. Returns the output and any errors */
+pub fn format(format: &str, args: &[String]) -> (String, Vec<String>) {
+    let mut output = String::new();
+    let mut errors = Vec::new();
+    let mut args = args.iter();
+    loop {
+        let consumed = format_once(format, &mut args, &mut output, &mut errors);
+        // a format without conversions would loop forever
+        if !consumed || args.len() == 0 {
+            break;
+        }
+    }
+    return (output, errors);
+}
+
+/** Expands backslash escapes the way `%b` and `echo -e` do. Returns the text and whether a `\c` asked to stop output */
+pub fn unescape(text: &str) -> (String, bool) {
+    let mut output = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        if char == '\\' {
+            if !push_escape(&mut chars, &mut output, true) {
+                return (output, true);
+            }
+        } else {
+            output.push(char);
+        }
+    }
+    return (output, false);
+}
+
+/** Removes backslashes, keeping the character each one escapes, like `read` without -r */
+pub fn remove_backslashes(text: &str) -> String {
+    let mut output = String::new();
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char == '\\' {
+            if let Some(escaped) = chars.next() {
+                output.push(escaped);
+            }
+        } else {
+            output.push(char);
+        }
+    }
+    return output;
+}
+
+/** Runs through the format once. Returns if any args were consumed */
+fn format_once(
+    format: &str,
+    args: &mut Iter<String>,
+    output: &mut String,
+    errors: &mut Vec<String>,
+) -> bool {
+    let mut consumed = false;
+    let mut chars = format.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => {
+                push_escape(&mut chars, output, false);
+            }
+            '%' => {
+                if chars.peek() == Some(&'%') {
+                    chars.next();
+                    output.push('%');
+                    continue;
+                }
+
+                let spec = Spec::parse(&mut chars);
+                let conversion = match chars.next() {
+                    Some(conversion) => conversion,
+                    None => {
+                        errors.push("`%': missing format character".to_string());
+                        break;
+                    }
+                };
+                let arg = args.next();
+                consumed |= arg.is_some();
+                match spec.convert(conversion, arg.map(|a| a.as_str()).unwrap_or(""), errors) {
+                    Some(converted) => output.push_str(&converted),
+                    // bash stops formatting at an invalid conversion
+                    None => break,
+                }
+            }
+            _ => output.push(char),
+        }
+    }
+    return consumed;
+}
+
+/** Pushes the escape following a `\`. `\c` and `\0NNN` octals are only understood by `%b` (argument) escapes. Returns false on `\c` */
+fn push_escape(chars: &mut Peekable<Chars>, output: &mut String, argument: bool) -> bool {
+    let char = match chars.next() {
+        Some(char) => char,
+        None => {
+            output.push('\\');
+            return true;
+        }
+    };
+    match char {
+        '\\' => output.push('\\'),
+        'a' => output.push('\x07'),
+        'b' => output.push('\x08'),
+        'e' | 'E' => output.push('\x1b'),
+        'f' => output.push('\x0c'),
+        'n' => output.push('\n'),
+        'r' => output.push('\r'),
+        't' => output.push('\t'),
+        'v' => output.push('\x0b'),
+        '"' if !argument => output.push('"'),
+        '\'' if !argument => output.push('\''),
+        'c' if argument => return false,
+        '0'..='7' => {
+            // %b octals are written \0NNN, format octals \NNN
+            let (mut value, max_digits) = if argument && char == '0' {
+                (0, 3)
+            } else {
+                (char.to_digit(8).unwrap(), 2)
+            };
+            for _ in 0..max_digits {
+                match chars.peek().and_then(|c| c.to_digit(8)) {
+                    Some(digit) => {
+                        value = value * 8 + digit;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            output.push(char::from_u32(value & 0xff).unwrap());
+        }
+        'x' => {
+            let mut value = 0;
+            let mut digits = 0;
+            while digits < 2 {
+                match chars.peek().and_then(|c| c.to_digit(16)) {
+                    Some(digit) => {
+                        value = value * 16 + digit;
+                        chars.next();
+                        digits += 1;
+                    }
+                    None => break,
+                }
+            }
+            if digits == 0 {
+                output.push_str("\\x");
+            } else {
+                output.push(char::from_u32(value).unwrap());
+            }
+        }
+        _ => {
+            output.push('\\');
+            output.push(char);
+        }
+    }
+    return true;
+}
+
+/** The flags, width and precision between a `%` and its conversion character */
+#[derive(Default)]
+struct Spec {
+    left_align: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    alternate: bool,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn parse(chars: &mut Peekable<Chars>) -> Spec {
+        let mut spec = Spec::default();
+        while let Some(char) = chars.peek() {
+            match char {
+                '-' => spec.left_align = true,
+                '+' => spec.plus_sign = true,
+                ' ' => spec.space_sign = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero_pad = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = parse_digits(chars).unwrap_or(0);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(parse_digits(chars).unwrap_or(0));
+        }
+        return spec;
+    }
+
+    /** Converts a single argument. Returns None for an unknown conversion character */
+    fn convert(&self, conversion: char, arg: &str, errors: &mut Vec<String>) -> Option<String> {
+        return Some(match conversion {
+            's' => {
+                let text = match self.precision {
+                    Some(precision) => arg.chars().take(precision).collect(),
+                    None => arg.to_string(),
+                };
+                self.pad(String::new(), text, false)
+            }
+            'b' => self.pad(String::new(), unescape(arg).0, false),
+            'c' => self.pad(String::new(), arg.chars().take(1).collect(), false),
+            'd' | 'i' => {
+                let value = parse_integer(arg, errors);
+                let mut digits = value.unsigned_abs().to_string();
+                if let Some(precision) = self.precision {
+                    digits = format!("{:0>1$}", digits, precision);
+                }
+                self.pad(self.sign(value < 0), digits, self.precision.is_none())
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                let value = parse_integer(arg, errors) as u64;
+                let mut digits = match conversion {
+                    'o' => format!("{:o}", value),
+                    'x' => format!("{:x}", value),
+                    'X' => format!("{:X}", value),
+                    _ => value.to_string(),
+                };
+                if let Some(precision) = self.precision {
+                    digits = format!("{:0>1$}", digits, precision);
+                }
+                let prefix = match conversion {
+                    'o' if self.alternate && !digits.starts_with('0') => "0",
+                    'x' if self.alternate && value != 0 => "0x",
+                    'X' if self.alternate && value != 0 => "0X",
+                    _ => "",
+                };
+                self.pad(prefix.to_string(), digits, self.precision.is_none())
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = parse_float(arg, errors);
+                let precision = self.precision.unwrap_or(6);
+                let digits = if !value.is_finite() {
+                    value.abs().to_string().to_lowercase()
+                } else {
+                    match conversion {
+                        'f' | 'F' => format!("{:.*}", precision, value.abs()),
+                        'e' | 'E' => format_exponent(value.abs(), precision),
+                        _ => self.format_general(value.abs(), precision),
+                    }
+                };
+                let digits = if conversion.is_ascii_uppercase() {
+                    digits.to_uppercase()
+                } else {
+                    digits
+                };
+                self.pad(
+                    self.sign(value.is_sign_negative() && value != 0.0),
+                    digits,
+                    value.is_finite(),
+                )
+            }
+            _ => {
+                errors.push(format!("`{}': invalid format character", conversion));
+                return None;
+            }
+        });
+    }
+
+    fn sign(&self, negative: bool) -> String {
+        return if negative {
+            "-"
+        } else if self.plus_sign {
+            "+"
+        } else if self.space_sign {
+            " "
+        } else {
+            ""
+        }
+        .to_string();
+    }
+
+    /** Pads to the width. Zero padding goes between the prefix (sign) and the digits */
+    fn pad(&self, prefix: String, text: String, zero_padable: bool) -> String {
+        let length = prefix.chars().count() + text.chars().count();
+        if length >= self.width {
+            return prefix + &text;
+        }
+        let padding = self.width - length;
+        if self.left_align {
+            return prefix + &text + &" ".repeat(padding);
+        }
+        if self.zero_pad && zero_padable {
+            return prefix + &"0".repeat(padding) + &text;
+        }
+        return " ".repeat(padding) + &prefix + &text;
+    }
+
+    /** %g picks %e or %f based on the exponent, then drops trailing zeros unless # was given */
+    fn format_general(&self, value: f64, precision: usize) -> String {
+        let precision = precision.max(1);
+        let exponent = if value == 0.0 {
+            0
+        } else {
+            let formatted = format!("{:.*e}", precision - 1, value);
+            formatted.split_once('e').unwrap().1.parse::<i32>().unwrap()
+        };
+        let mut formatted = if exponent < -4 || exponent >= precision as i32 {
+            format_exponent(value, precision - 1)
+        } else {
+            format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+        };
+        if !self.alternate && formatted.contains('.') {
+            let (mantissa, exponent) = match formatted.split_once('e') {
+                Some((mantissa, exponent)) => (mantissa.to_string(), format!("e{}", exponent)),
+                None => (formatted.clone(), String::new()),
+            };
+            formatted = mantissa
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+                + &exponent;
+        }
+        return formatted;
+    }
+}
+
+/** C style exponent notation, which always has a sign and at least two exponent digits */
+fn format_exponent(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    return format!("{}e{}{:02}", mantissa, sign, exponent.abs());
+}
+
+fn parse_digits(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut digits = String::new();
+    while let Some(char) = chars.peek() {
+        if !char.is_ascii_digit() {
+            break;
+        }
+        digits.push(*char);
+        chars.next();
+    }
+    return digits.parse().ok();
+}
+
+/** Parses decimal, 0x hex, 0 octal, or a leading quote for the character's code like bash */
+fn parse_integer(arg: &str, errors: &mut Vec<String>) -> i64 {
+    let trimmed = arg.trim();
+    if trimmed.is_empty() {
+        return 0;
+    }
+    if trimmed.starts_with('\'') || trimmed.starts_with('"') {
+        return trimmed.chars().nth(1).map(|c| c as i64).unwrap_or(0);
+    }
+
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<i64>()
+    };
+    return match parsed {
+        Ok(value) if negative => -value,
+        Ok(value) => value,
+        Err(_) => {
+            errors.push(format!("{}: invalid number", arg));
+            0
+        }
+    };
+}
+
+fn parse_float(arg: &str, errors: &mut Vec<String>) -> f64 {
+    let trimmed = arg.trim();
+    if trimmed.is_empty() {
+        return 0.0;
+    }
+    if trimmed.starts_with('\'') || trimmed.starts_with('"') {
+        return trimmed
+            .chars()
+            .nth(1)
+            .map(|c| c as u32 as f64)
+            .unwrap_or(0.0);
+    }
+    return match trimmed.parse::<f64>() {
+        Ok(value) => value,
+        Err(_) => {
+            errors.push(format!("{}: invalid number", arg));
+            0.0
+        }
+    };
+}
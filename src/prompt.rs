@@ -0,0 +1,95 @@
+use std::{env, ffi::CStr, path::Path};
+
+use crate::shell::{self, Shell};
+
+/** Renders the prompt from `PS1`, defaulting to "$ " when it isn't set */
+pub fn render(shell: &Shell) -> String {
+    return render_variable(shell, "PS1", "$ ");
+}
+
+/** Renders the prompt for continuing a command on the next line (like a heredoc) from `PS2`, defaulting to "> " */
+pub fn render_continuation(shell: &Shell) -> String {
+    return render_variable(shell, "PS2", "> ");
+}
+
+fn render_variable(shell: &Shell, variable: &str, default: &str) -> String {
+    let ps = match shell.var(variable) {
+        Some(ps) => ps,
+        None => return default.to_string(),
+    };
+
+    let mut prompt = String::new();
+    let mut chars = ps.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            prompt.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('w') => prompt.push_str(&working_directory()),
+            Some('W') => prompt.push_str(&working_directory_name()),
+            Some('u') => prompt.push_str(&username()),
+            Some('h') => prompt.push_str(hostname().split('.').next().unwrap()),
+            Some('H') => prompt.push_str(&hostname()),
+            Some('$') => prompt.push(if unsafe { libc::geteuid() } == 0 {
+                '#'
+            } else {
+                '$'
+            }),
+            Some('n') => prompt.push('\n'),
+            Some('\\') => prompt.push('\\'),
+            Some(other) => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    return prompt;
+}
+
+/** The current directory with the home directory abbreviated to ~ */
+fn working_directory() -> String {
+    let cwd = shell::working_dir();
+    if let Some(home) = env::var_os("HOME") {
+        if let Ok(relative) = cwd.strip_prefix(&home) {
+            if relative.as_os_str().is_empty() {
+                return "~".to_string();
+            }
+            return Path::new("~").join(relative).display().to_string();
+        }
+    }
+    return cwd.display().to_string();
+}
+
+fn working_directory_name() -> String {
+    let cwd = shell::working_dir();
+    if env::var_os("HOME").map(|home| cwd == Path::new(&home)) == Some(true) {
+        return "~".to_string();
+    }
+    return match cwd.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => cwd.display().to_string(),
+    };
+}
+
+fn username() -> String {
+    let passwd = unsafe { libc::getpwuid(libc::geteuid()) };
+    if passwd.is_null() {
+        return env::var("USER").unwrap_or_default();
+    }
+    return unsafe { CStr::from_ptr((*passwd).pw_name) }
+        .to_string_lossy()
+        .into_owned();
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return String::new();
+    }
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    return String::from_utf8_lossy(&buffer[..end]).into_owned();
+}
@@ -0,0 +1,429 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    ffi::{CStr, OsStr, OsString},
+    fs,
+    io::ErrorKind,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use rustyline::{config::Configurer, history::FileHistory, Editor};
+
+use crate::{
+    commands::{self, Command},
+    completer::Completer,
+    hash::HashTable,
+    jobs::Jobs,
+    signal,
+};
+
+/** State that lives for the whole shell session and is shared with running commands */
+pub struct Shell {
+    pub editor: Editor<Completer, FileHistory>,
+    /** None when there's no home directory to keep history in */
+    history_path: Option<PathBuf>,
+    /** $0 */
+    pub shell_name: String,
+    /** $1, $2, ... */
+    pub positional_parameters: Vec<String>,
+    /** Alias names to the text they stand for */
+    pub aliases: HashMap<String, String>,
+    /** Options changed with `set` */
+    pub options: Options,
+    /** Commands running in the background */
+    pub jobs: Jobs,
+    /** How many evals deep the running command is, to stop runaway recursion */
+    pub eval_depth: usize,
+    /** Directories saved by `pushd`, the most recent last. The current directory isn't included */
+    pub directory_stack: Vec<PathBuf>,
+    /** The exit status of the last command run from the prompt or a script */
+    pub last_status: i32,
+    /** Where commands have been found on PATH */
+    pub hash_table: HashTable,
+    /** File descriptors past stderr opened by redirects around the running command, like `3>file`. Programs get
+    them when they start */
+    pub redirected_fds: BTreeMap<i32, commands::IO>,
+    /** Variables declared with `declare -i`, whose assigned values are evaluated as arithmetic */
+    pub integer_variables: HashSet<String>,
+    /** Variables that haven't been exported, so programs don't get them. Exported ones are in the environment */
+    pub variables: HashMap<String, String>,
+    /** Set while a command after `&` starts, so its programs get a process group of their own and the terminal's Ctrl-C
+    doesn't reach them */
+    pub background: bool,
+    /** Commands set with `trap`, by the signal they're for: `SIGINT`, `SIGTERM`, `SIGHUP`, or `EXIT` for when the
+    shell exits */
+    pub traps: BTreeMap<String, String>,
+}
+
+impl Shell {
+    pub fn new() -> Shell {
+        let mut editor = Editor::new().unwrap();
+        editor.set_helper(Some(Completer::new()));
+        editor.set_completion_type(rustyline::CompletionType::List);
+        editor.set_max_history_size(history_size()).unwrap();
+
+        // PWD might have come from somewhere else, or not be set at all
+        if logical_dir().is_none() {
+            if let Ok(current_dir) = env::current_dir() {
+                env::set_var("PWD", current_dir);
+            }
+        }
+
+        let history_path = history_path();
+        // a missing history file just means this is the first session
+        if let Some(history_path) = &history_path {
+            let _ = editor.load_history(history_path);
+        }
+
+        return Shell {
+            editor,
+            history_path,
+            shell_name: env::args().next().unwrap_or_default(),
+            positional_parameters: Vec::new(),
+            aliases: HashMap::new(),
+            options: Options {
+                highlight: true,
+                ..Options::default()
+            },
+            jobs: Jobs::default(),
+            eval_depth: 0,
+            directory_stack: Vec::new(),
+            last_status: 0,
+            hash_table: HashTable::default(),
+            redirected_fds: BTreeMap::new(),
+            integer_variables: HashSet::new(),
+            variables: HashMap::new(),
+            background: false,
+            traps: BTreeMap::new(),
+        };
+    }
+
+    /** Sources the startup file, `SHELLRC` if set, otherwise ~/.shellrc. A missing file is skipped silently */
+    pub async fn load_rc(&mut self) {
+        // without a home directory there's nowhere to look
+        let rc_path = match env::var_os("SHELLRC") {
+            Some(path) => PathBuf::from(path),
+            None => match env::var_os("HOME").or_else(passwd_home) {
+                Some(home) => Path::new(&home).join(".shellrc"),
+                None => return,
+            },
+        };
+        if !rc_path.is_file() {
+            return;
+        }
+
+        let source = Command::Source(vec![rc_path.to_string_lossy().into_owned()]);
+        if let Some(exit_code) = source.run(self).await {
+            self.finish(exit_code).await;
+        }
+    }
+
+    /** Runs a script file instead of reading commands from the prompt, with the arguments after it as the positional
+    parameters. Exits with the status of the last command in it */
+    pub async fn run_script(&mut self, script: String, args: Vec<String>) -> ! {
+        if let Err(error) = fs::metadata(&script) {
+            let reason = match error.kind() {
+                ErrorKind::NotFound => "No such file or directory".to_string(),
+                _ => error.to_string(),
+            };
+            eprintln!("{}: {}: {}", self.shell_name, script, reason);
+            self.exit(127);
+        }
+
+        self.shell_name = script.clone();
+        self.positional_parameters = args;
+        let source = Command::Source(vec![script]);
+        if let Some(exit_code) = source.run(self).await {
+            self.finish(exit_code).await;
+        }
+        self.finish(self.last_status).await;
+    }
+
+    /** Runs the commands given with `-c` instead of reading them from the prompt. The arguments after them are $0 and
+    then the positional parameters, like other shells. Exits with the status of the last command */
+    pub async fn run_command_string(&mut self, commands: String, args: Vec<String>) -> ! {
+        let mut args = args.into_iter();
+        if let Some(name) = args.next() {
+            self.shell_name = name;
+        }
+        self.positional_parameters = args.collect();
+        if let Some(exit_code) = commands::run_text(&commands, self).await {
+            self.finish(exit_code).await;
+        }
+        self.finish(self.last_status).await;
+    }
+
+    /** Drops the first count positional parameters. Returns false, leaving them alone, if there aren't that many */
+    pub fn shift(&mut self, count: usize) -> bool {
+        if count > self.positional_parameters.len() {
+            return false;
+        }
+        self.positional_parameters.drain(..count);
+        return true;
+    }
+
+    /** Gives the line editor what it needs from the shell to highlight the line being typed */
+    pub fn sync_editor(&mut self) {
+        let aliases = self.aliases.keys().cloned().collect();
+        let jobs = self
+            .jobs
+            .iter()
+            .map(|job| (job.number, job.command.clone()))
+            .collect();
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.highlight = self.options.highlight;
+            helper.aliases = aliases;
+            helper.jobs = jobs;
+        }
+    }
+
+    /** Reads a command line from the prompt. Only these lines are checked for being finished and hinted from history
+    as they're typed */
+    pub fn read_command_line(&mut self, prompt: &str) -> rustyline::Result<String> {
+        self.set_command_line(true);
+        let line = self.editor.readline(prompt);
+        self.set_command_line(false);
+        return line;
+    }
+
+    fn set_command_line(&mut self, command_line: bool) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.command_line = command_line;
+        }
+    }
+
+    /** A variable's value, whether it's the shell's own or exported */
+    pub fn var(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+        return env::var(name).ok();
+    }
+
+    /** Sets a variable. One that's exported stays exported, anything else is the shell's own until it's exported */
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        if env::var_os(name).is_some() {
+            env::set_var(name, value);
+        } else {
+            self.variables.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /** Exports a variable so programs get it, with the given value or the one it already has. Exporting a name that
+    isn't set does nothing */
+    pub fn export_var(&mut self, name: &str, value: Option<&str>) {
+        let current = self.variables.remove(name);
+        if let Some(value) = value.map(str::to_string).or(current) {
+            env::set_var(name, value);
+        }
+    }
+
+    /** Stops exporting a variable, keeping it as the shell's own */
+    pub fn unexport_var(&mut self, name: &str) {
+        if let Some(value) = env::var_os(name) {
+            env::remove_var(name);
+            self.variables
+                .insert(name.to_string(), value.to_string_lossy().into_owned());
+        }
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.variables.remove(name);
+        env::remove_var(name);
+    }
+
+    /** Everything a subshell could change, so it can be put back once the subshell is done */
+    pub fn snapshot(&self) -> Snapshot {
+        // umask can only be read by setting it, so it's put straight back
+        let umask = unsafe { libc::umask(0) };
+        unsafe { libc::umask(umask) };
+        return Snapshot {
+            variables: env::vars_os().collect(),
+            shell_variables: self.variables.clone(),
+            current_dir: env::current_dir().ok(),
+            umask,
+            positional_parameters: self.positional_parameters.clone(),
+            aliases: self.aliases.clone(),
+            options: self.options.clone(),
+            directory_stack: self.directory_stack.clone(),
+            traps: self.traps.clone(),
+        };
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        for (name, _) in env::vars_os() {
+            env::remove_var(name);
+        }
+        for (name, value) in snapshot.variables {
+            env::set_var(name, value);
+        }
+        self.variables = snapshot.shell_variables;
+        if let Some(current_dir) = snapshot.current_dir {
+            let _ = env::set_current_dir(current_dir);
+        }
+        unsafe { libc::umask(snapshot.umask) };
+        self.positional_parameters = snapshot.positional_parameters;
+        self.aliases = snapshot.aliases;
+        self.options = snapshot.options;
+        self.directory_stack = snapshot.directory_stack;
+        self.traps = snapshot.traps;
+        signal::trap_interrupt(self.traps.get("SIGINT").map(|action| action.as_str()));
+        self.trap_terminations();
+    }
+
+    /** Catches or ignores SIGTERM and SIGHUP to go with the traps, see `signal::trap_termination` */
+    pub fn trap_terminations(&self) {
+        let exit_trapped = self.traps.contains_key("EXIT");
+        for (name, signal) in [("SIGTERM", libc::SIGTERM), ("SIGHUP", libc::SIGHUP)] {
+            let action = self.traps.get(name).map(|action| action.as_str());
+            signal::trap_termination(signal, action, exit_trapped);
+        }
+    }
+
+    /** Runs the `EXIT` trap, if one is set, and exits. `$?` in the trap is the exit code, and the trap can exit with a
+    different one */
+    pub async fn finish(&mut self, exit_code: i32) -> ! {
+        self.last_status = exit_code;
+        let exit_code = commands::run_trap("EXIT", self).await.unwrap_or(exit_code);
+        self.exit(exit_code);
+    }
+
+    /** Saves the history and exits the process */
+    pub fn exit(&mut self, exit_code: i32) -> ! {
+        if let Some(history_path) = &self.history_path {
+            if let Err(error) = self.editor.save_history(history_path) {
+                eprintln!("history: {}: {}", history_path.display(), error);
+            }
+        }
+        exit(exit_code);
+    }
+}
+
+/** The state of the shell at some point, see `Shell::snapshot` */
+pub struct Snapshot {
+    variables: Vec<(OsString, OsString)>,
+    shell_variables: HashMap<String, String>,
+    current_dir: Option<PathBuf>,
+    umask: libc::mode_t,
+    positional_parameters: Vec<String>,
+    aliases: HashMap<String, String>,
+    options: Options,
+    directory_stack: Vec<PathBuf>,
+    traps: BTreeMap<String, String>,
+}
+
+/** Shell options, set with `set -o name` or their single letter flags */
+#[derive(Default, Clone)]
+pub struct Options {
+    /** -e, exit when a command fails */
+    pub errexit: bool,
+    /** -x, print each command before running it */
+    pub xtrace: bool,
+    /** A pipeline fails if any command in it fails, not just the last */
+    pub pipefail: bool,
+    /** Redirecting output to a file creates any missing directories on the way to it. Off by default, like other
+    shells */
+    pub mkdir_redirect: bool,
+    /** `>` won't overwrite a file that already exists, `>|` still does */
+    pub noclobber: bool,
+    /** `>` asks before overwriting a file that isn't empty, when someone is typing at the shell. Off by default */
+    pub confirm_overwrite: bool,
+    /** Color the command line while it's typed. On by default */
+    pub highlight: bool,
+}
+
+impl Options {
+    /** Turns the option on or off by its long name. Returns false if there's no option with that name */
+    pub fn set(&mut self, name: &str, enable: bool) -> bool {
+        let option = match name {
+            "errexit" => &mut self.errexit,
+            "xtrace" => &mut self.xtrace,
+            "pipefail" => &mut self.pipefail,
+            "mkdir-redirect" => &mut self.mkdir_redirect,
+            "noclobber" => &mut self.noclobber,
+            "confirm-overwrite" => &mut self.confirm_overwrite,
+            "highlight" => &mut self.highlight,
+            _ => return false,
+        };
+        *option = enable;
+        return true;
+    }
+
+    /** Every option's long name and whether it's on */
+    pub fn all(&self) -> [(&'static str, bool); 7] {
+        return [
+            ("confirm-overwrite", self.confirm_overwrite),
+            ("errexit", self.errexit),
+            ("highlight", self.highlight),
+            ("mkdir-redirect", self.mkdir_redirect),
+            ("noclobber", self.noclobber),
+            ("pipefail", self.pipefail),
+            ("xtrace", self.xtrace),
+        ];
+    }
+
+    /** Every option and whether it's on, the way `set -o` shows them */
+    pub fn list(&self) -> String {
+        let mut list = String::new();
+        for (name, enabled) in self.all() {
+            list += &format!("{:<15}\t{}\n", name, if enabled { "on" } else { "off" });
+        }
+        return list;
+    }
+}
+
+/** `PWD`, if it's an absolute path to the current directory. It keeps any symlinks cd went through, which the
+current directory from the OS doesn't */
+pub fn logical_dir() -> Option<PathBuf> {
+    let pwd = PathBuf::from(env::var_os("PWD")?);
+    if !pwd.is_absolute() {
+        return None;
+    }
+    let pwd_metadata = fs::metadata(&pwd).ok()?;
+    let current_metadata = fs::metadata(".").ok()?;
+    let same = pwd_metadata.dev() == current_metadata.dev()
+        && pwd_metadata.ino() == current_metadata.ino();
+    return same.then_some(pwd);
+}
+
+/** The directory the shell is in, through symlinks like `logical_dir` when it can be. A deleted directory has no real
+path, so then `$PWD` is used even though it's gone (or `/` without it), and `cd ..` or `cd /tmp` can still get out */
+pub fn working_dir() -> PathBuf {
+    return logical_dir()
+        .or_else(|| env::current_dir().ok())
+        .or_else(|| {
+            env::var_os("PWD")
+                .map(PathBuf::from)
+                .filter(|pwd| pwd.is_absolute())
+        })
+        .unwrap_or_else(|| PathBuf::from("/"));
+}
+
+/** The current user's home directory from the password database */
+pub fn passwd_home() -> Option<OsString> {
+    let passwd = unsafe { libc::getpwuid(libc::getuid()) };
+    if passwd.is_null() {
+        return None;
+    }
+    let home = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+    return Some(OsStr::from_bytes(home.to_bytes()).to_os_string());
+}
+
+/** The history file, `HISTFILE` if set, otherwise ~/.codecrafters_shell_history. None if there's no home directory */
+fn history_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("HISTFILE") {
+        return Some(PathBuf::from(path));
+    }
+    let home = env::var_os("HOME").or_else(passwd_home)?;
+    return Some(Path::new(&home).join(".codecrafters_shell_history"));
+}
+
+/** The number of lines kept in the history (and its file), from `HISTSIZE` with a default of 1000 */
+fn history_size() -> usize {
+    return env::var("HISTSIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(1000);
+}
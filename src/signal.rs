@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+/** Signal names (without the SIG prefix) and their numbers, in number order */
+const SIGNALS: [(&str, libc::c_int); 19] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+];
+
+/** Parses a signal number or name, with or without the SIG prefix, in any case */
+pub fn parse(signal: &str) -> Option<libc::c_int> {
+    // numbers without a name here (like real-time signals) are still valid, 0 just checks the process exists
+    if let Ok(number) = signal.parse::<libc::c_int>() {
+        return (0..=libc::SIGRTMAX()).contains(&number).then_some(number);
+    }
+    let signal = signal.to_ascii_uppercase();
+    let name = signal.strip_prefix("SIG").unwrap_or(&signal);
+    return SIGNALS
+        .iter()
+        .find(|(signal_name, _)| *signal_name == name)
+        .map(|(_, signal_number)| *signal_number);
+}
+
+/** The name of every known signal, for `kill -l` */
+pub fn names() -> Vec<&'static str> {
+    return SIGNALS.iter().map(|(name, _)| *name).collect();
+}
+
+/** Set when Ctrl-C is pressed while a command runs, until the shell gets back to the prompt */
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/** SIGTERM and SIGHUP that have come in and been caught but not handled yet, a bit for each by its number */
+static TERMINATED: AtomicI32 = AtomicI32::new(0);
+/** Set once the signals are set up for the prompt, so programs the shell starts get the defaults back */
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/** Sets up signals for someone typing at the prompt. Ctrl-C stops the command in the foreground instead of the shell:
+the terminal sends SIGINT to the programs it's running as well as the shell, and a handler (unlike ignoring it) goes
+back to the default when a program starts, so they still stop while the shell only notes it and gives up on the rest of
+the line. Ctrl-\ doesn't quit the shell, SIGTTOU is ignored so the shell can take the terminal back from a job
+brought to the foreground, and SIGTERM is ignored so `kill` without a signal doesn't close it */
+pub fn init_interactive() {
+    INTERACTIVE.store(true, Ordering::SeqCst);
+    catch_interrupts();
+    unsafe {
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::signal(libc::SIGTERM, libc::SIG_IGN);
+    }
+}
+
+/** Changes what SIGINT does for `trap`. A command means it's caught so the command can run, an empty one means it's
+ignored, and None puts back what the shell started with. At the prompt it's always caught, so Ctrl-C still only stops
+the command in the foreground */
+pub fn trap_interrupt(action: Option<&str>) {
+    let interactive = INTERACTIVE.load(Ordering::SeqCst);
+    match action {
+        Some("") if !interactive => unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+        },
+        None if !interactive => unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        },
+        _ => catch_interrupts(),
+    }
+}
+
+/** Changes what SIGTERM or SIGHUP does for `trap`. It's caught while there's a command for it to run, either its own
+trap or the EXIT trap since it would end the shell, and an empty command means it's ignored. Otherwise it's back to the
+default, except that an interactive shell keeps ignoring SIGTERM */
+pub fn trap_termination(signal: libc::c_int, action: Option<&str>, exit_trapped: bool) {
+    let handler = match action {
+        Some("") => libc::SIG_IGN,
+        Some(_) => on_termination as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        None if exit_trapped => on_termination as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        None if signal == libc::SIGTERM && INTERACTIVE.load(Ordering::SeqCst) => libc::SIG_IGN,
+        None => libc::SIG_DFL,
+    };
+    unsafe { libc::signal(signal, handler) };
+}
+
+fn catch_interrupts() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // blocking calls the shell itself makes carry on instead of failing
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+/** Puts back the default for the signals the prompt changed, in a program that's about to start. Ignored signals stay
+ignored through exec, so without this Ctrl-\ couldn't quit anything run from the prompt. It's called between fork and
+exec, so it only uses signal, which is safe there */
+pub fn reset_for_child() {
+    if !INTERACTIVE.load(Ordering::SeqCst) {
+        return;
+    }
+    for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTTOU, libc::SIGTERM] {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_termination(signal: libc::c_int) {
+    TERMINATED.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/** Takes a SIGTERM or SIGHUP that came in, if there was one, so it's only handled once */
+pub fn take_termination() -> Option<libc::c_int> {
+    let pending = TERMINATED.load(Ordering::SeqCst);
+    if pending == 0 {
+        return None;
+    }
+    let signal = pending.trailing_zeros() as libc::c_int;
+    TERMINATED.fetch_and(!(1 << signal), Ordering::SeqCst);
+    return Some(signal);
+}
+
+/** If Ctrl-C was pressed since the line started running, so loops and lists should stop */
+pub fn interrupted() -> bool {
+    return INTERRUPTED.load(Ordering::SeqCst);
+}
+
+/** Starts a new line without an interrupt pending */
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
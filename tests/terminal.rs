@@ -0,0 +1,113 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    process::{Command, Stdio},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/** Starts the shell on a new terminal, as if someone were typing at it. Returns the shell and the other end of the
+terminal, which reads what the shell writes and writes what it reads */
+fn spawn_on_terminal() -> (std::process::Child, File) {
+    let (mut controller, mut terminal) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut controller,
+            &mut terminal,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0, "couldn't open a terminal");
+    let terminal = unsafe { OwnedFd::from_raw_fd(terminal) };
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"));
+    command
+        .env("HISTFILE", "/dev/null")
+        .stdin(Stdio::from(terminal.try_clone().unwrap()))
+        .stdout(Stdio::from(terminal.try_clone().unwrap()))
+        .stderr(Stdio::from(terminal));
+    // its own session with the terminal as the controlling one, like a login shell
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            libc::ioctl(0, libc::TIOCSCTTY, 0);
+            return Ok(());
+        })
+    };
+    let shell = command.spawn().unwrap();
+    return (shell, unsafe { File::from_raw_fd(controller) });
+}
+
+/** Reads everything the shell writes to the terminal, until it's exited */
+fn read_output(terminal: &File) -> JoinHandle<String> {
+    let mut reader = terminal.try_clone().unwrap();
+    return thread::spawn(move || {
+        // reading fails once the shell has exited and the terminal has no one on the other end
+        let mut output = Vec::new();
+        let _ = reader.read_to_end(&mut output);
+        return String::from_utf8_lossy(&output).into_owned();
+    });
+}
+
+#[test]
+fn exit_trap_runs_on_sigterm() {
+    let (mut shell, mut terminal) = spawn_on_terminal();
+    let output = read_output(&terminal);
+
+    // quoted so what's echoed back as it's typed doesn't look like the trap running
+    terminal.write_all(b"trap 'echo trap''ped' EXIT\n").unwrap();
+    terminal.write_all(b"sleep 1\n").unwrap();
+    thread::sleep(Duration::from_millis(500));
+    unsafe { libc::kill(shell.id() as libc::pid_t, libc::SIGTERM) };
+
+    let status = shell.wait().unwrap();
+    drop(terminal);
+    assert_eq!(
+        status.code(),
+        Some(128 + libc::SIGTERM),
+        "{:?}",
+        status.signal()
+    );
+    assert!(output.join().unwrap().contains("trapped"));
+}
+
+#[test]
+fn confirms_before_overwriting() {
+    let file = std::env::temp_dir().join(format!("confirm-overwrite-{}", std::process::id()));
+    let path = file.display();
+    let (mut shell, mut terminal) = spawn_on_terminal();
+    let output = read_output(&terminal);
+
+    let input = format!(
+        "set -o confirm-overwrite\n\
+        echo old > {path}\n\
+        echo new > {path}\nn\n\
+        cat {path}\n\
+        echo new > {path}\ny\n\
+        exit\n"
+    );
+    terminal.write_all(input.as_bytes()).unwrap();
+
+    let status = shell.wait().unwrap();
+    drop(terminal);
+    let output = output.join().unwrap();
+    let content = std::fs::read_to_string(&file).unwrap();
+    let _ = std::fs::remove_file(&file);
+    assert!(status.success());
+    // the file didn't exist the first time, so it's only asked about twice
+    assert_eq!(
+        output
+            .matches(&format!("overwrite {}? (y/n)", path))
+            .count(),
+        2
+    );
+    assert!(output.contains("not overwritten"));
+    assert!(output.contains("old\r\n"));
+    assert_eq!(content, "new\n");
+}